@@ -0,0 +1,273 @@
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+use super::error::ExcelError;
+use super::formula;
+
+// Excel sheet limits
+pub const MAX_ROWS: u32 = 1_048_576;
+pub const MAX_COLS: u32 = 16_384;
+
+
+// A cell address such as "B3". Row and col are zero-based like the rest of the API,
+// use from_one_based()/one_based() for openpyxl-style coordinates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Address {
+    pub row: u32,
+    pub col: u32,
+}
+
+impl Address {
+    pub fn new(row: u32, col: u32) -> Self {
+        Self { row, col }
+    }
+
+    pub fn from_one_based(row: u32, col: u32) -> Result<Self, ExcelError> {
+        if row == 0 || col == 0 || row > MAX_ROWS || col > MAX_COLS {
+            return Err(ExcelError::AddressOutOfRange { row: row as usize, col: col as usize });
+        }
+        Ok(Self { row: row - 1, col: col - 1 })
+    }
+
+    // (row, col) counted from 1
+    pub fn one_based(&self) -> (u32, u32) {
+        (self.row + 1, self.col + 1)
+    }
+}
+
+impl From<(u32, u32)> for Address {
+    fn from((row, col): (u32, u32)) -> Self {
+        Self { row, col }
+    }
+}
+
+impl From<Address> for (u32, u32) {
+    fn from(a: Address) -> Self {
+        (a.row, a.col)
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", column_name(self.col), self.row + 1)
+    }
+}
+
+// "B3", "$AA$10"
+impl FromStr for Address {
+    type Err = ExcelError;
+
+    fn from_str(s: &str) -> Result<Self, ExcelError> {
+        let invalid = || ExcelError::InvalidAddress(s.to_string());
+        let cell = s.strip_prefix('$').unwrap_or(s);
+        let split = cell.find(|c: char| !c.is_ascii_alphabetic()).ok_or_else(invalid)?;
+        let (letters, digits) = cell.split_at(split);
+        let digits = digits.strip_prefix('$').unwrap_or(digits);
+        let col = parse_column(letters).ok_or_else(invalid)?;
+        let row = parse_row(digits).ok_or_else(invalid)?;
+        Ok(Self { row, col })
+    }
+}
+
+
+// A rectangular range such as "A1:C10", "Sheet1!B2" or the whole column "C:C", both ends included
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CellRange {
+    pub sheet: Option<String>,
+    pub start: Address,
+    pub end: Address,
+}
+
+impl CellRange {
+    pub fn new(start: Address, end: Address) -> Self {
+        Self { sheet: None, start, end }
+    }
+
+    pub fn on_sheet(mut self, sheetname: &str) -> Self {
+        self.sheet = Some(sheetname.to_string());
+        self
+    }
+
+    pub fn rows(&self) -> RangeInclusive<u32> {
+        self.start.row..=self.end.row
+    }
+
+    pub fn cols(&self) -> RangeInclusive<u32> {
+        self.start.col..=self.end.col
+    }
+
+    pub fn contains(&self, address: Address) -> bool {
+        self.rows().contains(&address.row) && self.cols().contains(&address.col)
+    }
+
+    // the sheet name, required by the *_in read methods
+    pub fn sheetname(&self) -> Result<&str, ExcelError> {
+        self.sheet.as_deref().ok_or_else(|| ExcelError::InvalidAddress(format!("{} has no sheet name", self)))
+    }
+}
+
+impl From<Address> for CellRange {
+    fn from(a: Address) -> Self {
+        Self::new(a, a)
+    }
+}
+
+impl fmt::Display for CellRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(ref sheet) = self.sheet {
+            write!(f, "{}", formula::sheet_prefix(&[sheet]))?;
+        }
+        let whole_cols = self.start.row == 0 && self.end.row == MAX_ROWS - 1;
+        let whole_rows = self.start.col == 0 && self.end.col == MAX_COLS - 1;
+        if whole_cols && !whole_rows {
+            write!(f, "{}:{}", column_name(self.start.col), column_name(self.end.col))
+        } else if whole_rows && !whole_cols {
+            write!(f, "{}:{}", self.start.row + 1, self.end.row + 1)
+        } else if self.start == self.end {
+            write!(f, "{}", self.start)
+        } else {
+            write!(f, "{}:{}", self.start, self.end)
+        }
+    }
+}
+
+// "A1:C10", "Sheet1!A1:C10", "'My sheet'!B2", "C:C", "3:5"
+impl FromStr for CellRange {
+    type Err = ExcelError;
+
+    fn from_str(s: &str) -> Result<Self, ExcelError> {
+        let invalid = || ExcelError::InvalidAddress(s.to_string());
+        let (sheet, cells) = match s.rfind('!') {
+            Some(i) => {
+                let sheet = &s[..i];
+                let sheet = match sheet.strip_prefix('\'').and_then(|q| q.strip_suffix('\'')) {
+                    Some(quoted) => quoted.replace("''", "'"),
+                    None => sheet.to_string(),
+                };
+                (Some(sheet), &s[i + 1..])
+            }
+            None => (None, s),
+        };
+        let (first, last) = match cells.find(':') {
+            Some(i) => (&cells[..i], &cells[i + 1..]),
+            None => (cells, cells),
+        };
+        let (first, last) = (first.replace('$', ""), last.replace('$', ""));
+        let (start, end) = if let (Some(a), Some(b)) = (parse_column(&first), parse_column(&last)) {
+            // whole columns
+            (Address::new(0, a), Address::new(MAX_ROWS - 1, b))
+        } else if let (Some(a), Some(b)) = (parse_row(&first), parse_row(&last)) {
+            // whole rows
+            (Address::new(a, 0), Address::new(b, MAX_COLS - 1))
+        } else {
+            (first.parse::<Address>().map_err(|_| invalid())?, last.parse::<Address>().map_err(|_| invalid())?)
+        };
+        if start.row > end.row || start.col > end.col {
+            return Err(invalid());
+        }
+        Ok(Self { sheet, start, end })
+    }
+}
+
+// 0 -> "A", 27 -> "AB"
+pub fn column_name(col: u32) -> String {
+    let mut letters = Vec::new();
+    let mut n = col + 1;
+    while n > 0 {
+        letters.push((b'A' + ((n - 1) % 26) as u8) as char);
+        n = (n - 1) / 26;
+    }
+    letters.iter().rev().collect()
+}
+
+// "AB" -> 27
+pub fn parse_column(letters: &str) -> Option<u32> {
+    if letters.is_empty() || !letters.chars().all(|c| c.is_ascii_alphabetic()) {
+        return None;
+    }
+    let col = letters.chars().try_fold(0u32, |acc, c| {
+        acc.checked_mul(26)?.checked_add(c.to_ascii_uppercase() as u32 - 'A' as u32 + 1)
+    })?;
+    Some(col - 1).filter(|&c| c < MAX_COLS)
+}
+
+// "12" -> 11
+pub fn parse_row(digits: &str) -> Option<u32> {
+    if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    digits.parse::<u32>().ok()?.checked_sub(1).filter(|&r| r < MAX_ROWS)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(s: &str) -> CellRange {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn addresses_parse_with_and_without_dollars() {
+        assert_eq!("B3".parse::<Address>().unwrap(), Address::new(2, 1));
+        assert_eq!("$AA$10".parse::<Address>().unwrap(), Address::new(9, 26));
+        assert_eq!("AA$10".parse::<Address>().unwrap(), Address::new(9, 26));
+        assert_eq!("aa10".parse::<Address>().unwrap(), Address::new(9, 26));
+        assert_eq!("XFD1048576".parse::<Address>().unwrap(), Address::new(MAX_ROWS - 1, MAX_COLS - 1));
+        assert_eq!(Address::new(9, 26).to_string(), "AA10");
+    }
+
+    #[test]
+    fn addresses_out_of_range_or_malformed_are_rejected() {
+        for s in &["XFE1", "ZZZZ1", "A0", "A1048577", "A99999999999", "$$A1", "A$$1", "1A", "A", "", "A1B", "Ä1"] {
+            assert!(s.parse::<Address>().is_err(), "{} parsed", s);
+        }
+        assert!(Address::from_one_based(0, 1).is_err());
+        assert!(Address::from_one_based(1, MAX_COLS + 1).is_err());
+        assert_eq!(Address::from_one_based(1, MAX_COLS).unwrap().one_based(), (1, MAX_COLS));
+    }
+
+    #[test]
+    fn ranges_parse_sheets_columns_and_rows() {
+        let r = range("'It''s'!A1");
+        assert_eq!(r.sheet.as_deref(), Some("It's"));
+        assert_eq!((r.start, r.end), (Address::new(0, 0), Address::new(0, 0)));
+        assert_eq!(r.to_string(), "'It''s'!A1");
+
+        let r = range("C:C");
+        assert_eq!((r.start, r.end), (Address::new(0, 2), Address::new(MAX_ROWS - 1, 2)));
+        assert_eq!(r.to_string(), "C:C");
+
+        let r = range("3:5");
+        assert_eq!((r.start, r.end), (Address::new(2, 0), Address::new(4, MAX_COLS - 1)));
+        assert_eq!(r.to_string(), "3:5");
+
+        let r = range("sheet1!$b$2:c$10");
+        assert_eq!(r.sheet.as_deref(), Some("sheet1"));
+        assert_eq!((r.start, r.end), (Address::new(1, 1), Address::new(9, 2)));
+        assert_eq!(r.to_string(), "sheet1!B2:C10");
+        assert_eq!(range("'My sheet'!$A:$B").to_string(), "'My sheet'!A:B");
+    }
+
+    #[test]
+    fn sheet_names_that_read_as_something_else_are_quoted() {
+        let on = |sheet: &str| CellRange::new(Address::new(0, 0), Address::new(1, 1)).on_sheet(sheet).to_string();
+        assert_eq!(on("2024"), "'2024'!A1:B2");
+        assert_eq!(on("A1"), "'A1'!A1:B2");
+        assert_eq!(on("R1C1"), "'R1C1'!A1:B2");
+        assert_eq!(on("rc"), "'rc'!A1:B2");
+        assert_eq!(on("Q1.2024"), "Q1.2024!A1:B2");
+        assert_eq!(on("_data"), "_data!A1:B2");
+        assert_eq!(range(&on("2024")).sheet.as_deref(), Some("2024"));
+    }
+
+    #[test]
+    fn ranges_out_of_range_or_reversed_are_rejected() {
+        for s in &["A1:XFE1", "XFE:XFE", "0:1", "1:1048577", "C3:A1", "B:A", "Sheet1!", "A1:"] {
+            assert!(s.parse::<CellRange>().is_err(), "{} parsed", s);
+        }
+        assert!(range("A1").sheetname().is_err());
+    }
+}
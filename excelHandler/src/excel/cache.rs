@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+use std::mem;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use calamine::{Data, Range};
+
+
+// Parsed sheets of a handle, so repeated finds don't decompress and parse the sheet XML again.
+// With a limit, the least recently used sheets are dropped once the estimated size exceeds it.
+// Shared by the threads of a parallel search, hence the lock.
+#[derive(Default)]
+pub struct SheetCache {
+    inner: Mutex<Sheets>,
+}
+
+#[derive(Default)]
+struct Sheets {
+    sheets: HashMap<String, (Arc<Range<Data>>, usize)>, // range and its estimated size
+    order: Vec<String>, // least recently used first
+    size: usize,
+    limit: Option<usize>,
+}
+
+impl SheetCache {
+    pub fn set_limit(&self, limit: Option<usize>) {
+        let mut inner = self.lock();
+        inner.limit = limit;
+        inner.shrink(0);
+    }
+
+    pub fn get(&self, sheetname: &str) -> Option<Arc<Range<Data>>> {
+        let mut inner = self.lock();
+        let range = inner.sheets.get(sheetname)?.0.clone();
+        inner.touch(sheetname);
+        Some(range)
+    }
+
+    // cache a parsed sheet, unless it is larger than the whole limit
+    pub fn insert(&self, sheetname: &str, range: Range<Data>) -> Arc<Range<Data>> {
+        let range = Arc::new(range);
+        let size = estimate_size(&range);
+        let mut inner = self.lock();
+        inner.evict(sheetname);
+        if inner.limit.is_some_and(|limit| size > limit) {
+            return range;
+        }
+        inner.shrink(size);
+        inner.sheets.insert(sheetname.to_string(), (range.clone(), size));
+        inner.order.push(sheetname.to_string());
+        inner.size += size;
+        range
+    }
+
+    pub fn evict(&self, sheetname: &str) {
+        self.lock().evict(sheetname);
+    }
+
+    pub fn clear(&self) {
+        let mut inner = self.lock();
+        inner.sheets.clear();
+        inner.order.clear();
+        inner.size = 0;
+    }
+
+    // estimated bytes held by the cached sheets
+    pub fn size(&self) -> usize {
+        self.lock().size
+    }
+
+    // the cache stays consistent even if a thread panicked while holding it
+    fn lock(&self) -> MutexGuard<'_, Sheets> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Sheets {
+    fn evict(&mut self, sheetname: &str) {
+        if let Some((_, size)) = self.sheets.remove(sheetname) {
+            self.size -= size;
+            self.order.retain(|s| s != sheetname);
+        }
+    }
+
+    fn touch(&mut self, sheetname: &str) {
+        if let Some(i) = self.order.iter().position(|s| s == sheetname) {
+            let name = self.order.remove(i);
+            self.order.push(name);
+        }
+    }
+
+    // drop old sheets until `extra` more bytes fit
+    fn shrink(&mut self, extra: usize) {
+        let limit = match self.limit {
+            Some(limit) => limit,
+            None => return,
+        };
+        while self.size + extra > limit && !self.order.is_empty() {
+            let oldest = self.order[0].clone();
+            self.evict(&oldest);
+        }
+    }
+}
+
+// cells are stored densely over the used range, plus the text of string cells
+fn estimate_size(range: &Range<Data>) -> usize {
+    let (height, width) = range.get_size();
+    let text: usize = range.used_cells().map(|(_, _, value)| match value {
+        Data::String(s) | Data::DateTimeIso(s) | Data::DurationIso(s) => s.capacity(),
+        _ => 0,
+    }).sum();
+    height * width * mem::size_of::<Data>() + text
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CELL: usize = mem::size_of::<Data>();
+
+    // a sheet of `cells` numbers in a row, CELL bytes each
+    fn sheet(cells: u32) -> Range<Data> {
+        let mut range = Range::new((0, 0), (0, cells - 1));
+        range.set_value((0, 0), Data::Float(1.0));
+        range
+    }
+
+    #[test]
+    fn least_recently_used_sheets_are_evicted_first() {
+        let cache = SheetCache::default();
+        cache.set_limit(Some(3 * CELL));
+        cache.insert("a", sheet(1));
+        cache.insert("b", sheet(1));
+        cache.insert("c", sheet(1));
+        assert!(cache.get("a").is_some()); // now b is the oldest
+        cache.insert("d", sheet(1));
+        assert!(cache.get("b").is_none());
+        assert_eq!(cache.size(), 3 * CELL);
+
+        cache.insert("e", sheet(2)); // c and then a go to make room
+        assert!(cache.get("c").is_none() && cache.get("a").is_none());
+        assert!(cache.get("d").is_some() && cache.get("e").is_some());
+        assert_eq!(cache.size(), 3 * CELL);
+    }
+
+    #[test]
+    fn sheets_larger_than_the_limit_are_not_kept() {
+        let cache = SheetCache::default();
+        cache.insert("a", sheet(2));
+        cache.insert("a", sheet(1)); // replaces the old entry
+        assert_eq!(cache.size(), CELL);
+        cache.set_limit(Some(2 * CELL));
+        let range = cache.insert("big", sheet(3));
+        assert_eq!(range.get_size(), (1, 3));
+        assert!(cache.get("big").is_none() && cache.get("a").is_some());
+
+        cache.insert("b", sheet(1));
+        cache.set_limit(Some(CELL)); // lowering the limit drops sheets right away
+        assert!(cache.get("a").is_none() && cache.get("b").is_some());
+        cache.evict("b");
+        assert_eq!(cache.size(), 0);
+        cache.insert("c", sheet(1));
+        cache.clear();
+        assert!(cache.get("c").is_none());
+        assert_eq!(cache.size(), 0);
+    }
+}
@@ -0,0 +1,717 @@
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::mem;
+
+use calamine::Data;
+
+use super::address::{Address, CellRange, MAX_COLS, MAX_ROWS};
+use super::dates::DateKind;
+use super::error::ExcelError;
+use super::input::CellInput;
+use super::lxw::{self, FormatId};
+use super::sheetname::validate_sheet_name;
+use super::style::CellStyle;
+
+
+// A workbook being created. Sheets are kept in memory so that they can still be
+// renamed, deleted or reordered, and written out with libxlsxwriter on save.
+// In constant-memory mode the rows go to libxlsxwriter as they are written instead, see Stream.
+#[derive(Default)]
+pub struct Book {
+    sheets: Vec<Sheet>,
+    stream: Option<Stream>, // constant-memory mode
+    styles: Vec<CellStyle>, // every distinct style applied to a cell
+    named: HashMap<String, CellStyle>,
+}
+
+// cell -> index into Book::styles
+type CellStyles = BTreeMap<(u32, u16), usize>;
+
+#[derive(Default)]
+struct Sheet {
+    name: String,
+    cells: BTreeMap<(u32, u16), CellInput>, // empty in constant-memory mode
+    styles: CellStyles, // in constant-memory mode only those of rows not written yet
+    rows: BTreeMap<u32, usize>, // whole rows styled, kept like `styles`
+    cols: BTreeMap<u16, usize>, // whole columns styled
+    arrays: Vec<CellRange>, // the ranges of the array formulas written
+    next_row: u32, // in constant-memory mode the first row that can still be written
+}
+
+impl Sheet {
+    // the style a cell shows: its own, else its row's, else its column's, as in Excel
+    fn style_at(&self, r: u32, c: u16) -> Option<usize> {
+        self.styles.get(&(r, c)).or_else(|| self.rows.get(&r)).or_else(|| self.cols.get(&c)).copied()
+    }
+
+    // the array formulas that writing `data` at (row, col) replaces, as their first cell is
+    // written; writing into the rest of one, or putting a new one over it, is an error
+    fn replaced_arrays(&self, row: u32, col: u32, data: &[Vec<CellInput>], new: &[CellRange])
+        -> Result<Vec<usize>, ExcelError>
+    {
+        let mut replaced = Vec::new();
+        for (i, range) in self.arrays.iter().enumerate() {
+            let values = |r: u32| r.checked_sub(row).and_then(|i| data.get(i as usize));
+            let first = range.start.col.checked_sub(col).map(|j| j as usize);
+            if first.is_some_and(|j| values(range.start.row).is_some_and(|values| j < values.len())) {
+                replaced.push(i);
+                continue;
+            }
+            let written = range.rows().filter_map(values).any(|values| {
+                values.iter().enumerate().any(|(j, value)| !value.is_empty() && range.cols().contains(&(col + j as u32)))
+            });
+            let covered = new.iter().any(|n| n.start.row <= range.end.row && range.start.row <= n.end.row
+                && n.start.col <= range.end.col && range.start.col <= n.end.col);
+            if written || covered {
+                return Err(ExcelError::ArrayFormulaPart(range.clone().on_sheet(&self.name)));
+            }
+        }
+        Ok(replaced)
+    }
+
+    // empty the cells of an array formula but its first one
+    fn clear_array(&mut self, range: &CellRange) {
+        let first = (range.start.row, range.start.col as u16);
+        for r in range.rows() {
+            let covered = (r, range.start.col as u16)..=(r, range.end.col as u16);
+            let covered: Vec<_> = self.cells.range(covered).map(|(&at, _)| at).filter(|&at| at != first).collect();
+            for at in covered {
+                self.cells.remove(&at);
+            }
+        }
+    }
+}
+
+// The libxlsxwriter workbook of a constant-memory Book. It is opened with constant_memory,
+// which keeps one row in memory and flushes it to a temporary file when a later row is
+// written. Sheet i of the Book is sheet i of the workbook, so sheets are only ever added
+// at the end.
+struct Stream {
+    workbook: lxw::Workbook,
+    formats: Formats,
+}
+
+impl Book {
+    // a constant-memory workbook, written to `path` on save
+    pub fn constant_memory(path: &str) -> Result<Self, ExcelError> {
+        let stream = Stream { workbook: lxw::Workbook::new(path, true)?, formats: Formats::default() };
+        Ok(Self { stream: Some(stream), ..Self::default() })
+    }
+
+    pub fn define_style(&mut self, name: &str, style: CellStyle) {
+        self.named.insert(name.to_string(), style);
+    }
+
+    pub fn named_style(&self, name: &str) -> Result<CellStyle, ExcelError> {
+        self.named.get(name).cloned().ok_or_else(|| ExcelError::StyleNotFound(name.to_string()))
+    }
+
+    fn style_index(&mut self, style: &CellStyle) -> usize {
+        match self.styles.iter().position(|s| s == style) {
+            Some(i) => i,
+            None => {
+                self.styles.push(style.clone());
+                self.styles.len() - 1
+            }
+        }
+    }
+
+    pub fn sheet_names(&self) -> Vec<String> {
+        self.sheets.iter().map(|s| s.name.clone()).collect()
+    }
+
+    pub fn position(&self, sheetname: &str) -> Result<usize, ExcelError> {
+        self.sheets.iter().position(|s| s.name == sheetname).ok_or_else(|| ExcelError::SheetNotFound(sheetname.to_string()))
+    }
+}
+
+// write a data matrix into a new workbook, top-left cell at (row, col),
+// giving the written cells `style` if any
+pub fn set_range_values_creater(book: &mut Book,
+        sheetname: &str,
+        row: u32,
+        col: u32,
+        data: &[Vec<CellInput>],
+        style: Option<&CellStyle>
+    )
+    -> Result<(), ExcelError>
+{
+    let arrays = array_ranges(row, col, data)?;
+    let i = sheet_index(book, sheetname)?;
+    let replaced = book.sheets[i].replaced_arrays(row, col, data, &arrays)?;
+    if let Some(style) = style {
+        for (i, values) in data.iter().enumerate().filter(|(_, values)| !values.is_empty()) {
+            let (r, last) = (row as usize + i, col as usize + values.len() - 1);
+            if r >= MAX_ROWS as usize || last >= MAX_COLS as usize {
+                return Err(ExcelError::AddressOutOfRange { row: r, col: last });
+            }
+            let range = CellRange::new(Address::new(r as u32, col), Address::new(r as u32, last as u32));
+            set_style_creater(book, sheetname, &range, style)?;
+        }
+    }
+    let sheet = &mut book.sheets[i];
+    if let Some(ref mut stream) = book.stream {
+        stream.write_rows(i, sheet, row, col, data, &book.styles)?;
+        // rows are written once, so a formula can't be replaced once its first row is out
+        sheet.arrays.extend(arrays);
+        return Ok(());
+    }
+    for i in replaced.into_iter().rev() {
+        let range = sheet.arrays.remove(i);
+        sheet.clear_array(&range);
+    }
+    for (i, values) in data.iter().enumerate() {
+        for (j, value) in values.iter().enumerate() {
+            let (r, c) = (row as usize + i, col as usize + j);
+            let address = match (u32::try_from(r), u16::try_from(c)) {
+                (Ok(r), Ok(c)) if r < MAX_ROWS && u32::from(c) < MAX_COLS => (r, c),
+                _ => return Err(ExcelError::AddressOutOfRange { row: r, col: c }),
+            };
+            value.check_finite(Address::new(address.0, u32::from(address.1)))?;
+            match *value {
+                ref value if value.is_empty() => sheet.cells.remove(&address),
+                ref value => sheet.cells.insert(address, value.clone()),
+            };
+        }
+    }
+    for range in &arrays {
+        sheet.clear_array(range);
+    }
+    sheet.arrays.extend(arrays);
+    Ok(())
+}
+
+// the ranges of the array formulas in `data`, whose other cells are left empty
+fn array_ranges(row: u32, col: u32, data: &[Vec<CellInput>]) -> Result<Vec<CellRange>, ExcelError> {
+    let mut ranges = Vec::new();
+    for (i, values) in data.iter().enumerate() {
+        for (j, value) in values.iter().enumerate() {
+            ranges.extend(value.array_range(row as usize + i, col as usize + j)?);
+        }
+    }
+    Ok(ranges)
+}
+
+// write `values` from column A into the row below the last one of the sheet
+pub fn append_row_creater(book: &mut Book, sheetname: &str, values: &[CellInput], style: Option<&CellStyle>)
+    -> Result<(), ExcelError>
+{
+    let row = match book.position(sheetname) {
+        Ok(i) if book.stream.is_some() => book.sheets[i].next_row,
+        Ok(i) => book.sheets[i].cells.keys().next_back().map_or(0, |&(r, _)| r + 1),
+        Err(_) => 0,
+    };
+    set_range_values_creater(book, sheetname, row, 0, &[values.to_vec()], style)
+}
+
+// give the cells of `range` a style, whether they have a value or not. Whole rows and
+// columns get a row or column format instead of a style per cell.
+pub fn set_style_creater(book: &mut Book, sheetname: &str, range: &CellRange, style: &CellStyle)
+    -> Result<(), ExcelError>
+{
+    let end = range.end;
+    if end.row >= MAX_ROWS || end.col >= MAX_COLS {
+        return Err(ExcelError::AddressOutOfRange { row: end.row as usize, col: end.col as usize });
+    }
+    let i = sheet_index(book, sheetname)?;
+    if book.stream.is_some() && range.start.row < book.sheets[i].next_row {
+        return Err(ExcelError::RowFlushed(range.start.row));
+    }
+    let style = book.style_index(style);
+    let sheet = &mut book.sheets[i];
+    let (first, whole_rows) = (range.start, range.start.col == 0 && end.col == MAX_COLS - 1);
+    if first.row == 0 && end.row == MAX_ROWS - 1 {
+        // what was styled in the columns before takes the new style, rows over them included
+        sheet.styles.retain(|&(_, c), _| u32::from(c) < first.col || u32::from(c) > end.col);
+        if whole_rows {
+            sheet.rows.clear();
+        }
+        for &r in sheet.rows.keys() {
+            for c in range.cols() {
+                sheet.styles.insert((r, c as u16), style);
+            }
+        }
+        for c in range.cols() {
+            sheet.cols.insert(c as u16, style);
+        }
+        if let Some(ref mut stream) = book.stream {
+            let format = stream.formats.get(&mut stream.workbook, &book.styles, (Some(style), None))?;
+            stream.workbook.set_column(i, first.col as u16, end.col as u16, format)?;
+        }
+    } else if whole_rows {
+        sheet.styles.retain(|&(r, _), _| r < first.row || r > end.row);
+        for r in range.rows() {
+            sheet.rows.insert(r, style);
+        }
+    } else {
+        for r in range.rows() {
+            for c in range.cols() {
+                sheet.styles.insert((r, c as u16), style);
+            }
+        }
+    }
+    Ok(())
+}
+
+// the position of a sheet, adding it at the end when missing
+fn sheet_index(book: &mut Book, sheetname: &str) -> Result<usize, ExcelError> {
+    match book.position(sheetname) {
+        Ok(i) => Ok(i),
+        Err(_) => {
+            add_sheet_creater(book, sheetname, book.sheets.len())?;
+            Ok(book.sheets.len() - 1)
+        }
+    }
+}
+
+// insert an empty sheet at `index` in the tab order
+pub fn add_sheet_creater(book: &mut Book, sheetname: &str, index: usize) -> Result<(), ExcelError> {
+    validate_sheet_name(sheetname, book.sheets.iter().map(|s| &s.name))?;
+    if let Some(ref mut stream) = book.stream {
+        if index != book.sheets.len() {
+            return Err(ExcelError::ConstantMemory("sheets can only be added after the last one"));
+        }
+        stream.workbook.add_worksheet(sheetname)?;
+    }
+    let sheet = Sheet { name: sheetname.to_string(), ..Sheet::default() };
+    book.sheets.insert(index, sheet);
+    Ok(())
+}
+
+pub fn rename_sheet_creater(book: &mut Book, cur_name: &str, new_name: &str) -> Result<(), ExcelError> {
+    let i = book.position(cur_name)?;
+    if book.stream.is_some() {
+        return Err(ExcelError::ConstantMemory("sheets can't be renamed"));
+    }
+    validate_sheet_name(new_name, book.sheets.iter().filter(|s| s.name != cur_name).map(|s| &s.name))?;
+    book.sheets[i].name = new_name.to_string();
+    Ok(())
+}
+
+pub fn delete_sheet_creater(book: &mut Book, sheetname: &str) -> Result<(), ExcelError> {
+    let i = book.position(sheetname)?;
+    if book.stream.is_some() {
+        return Err(ExcelError::ConstantMemory("sheets can't be deleted"));
+    }
+    book.sheets.remove(i);
+    Ok(())
+}
+
+// `sheetnames` lists every sheet in the new order
+pub fn reorder_sheets_creater(book: &mut Book, sheetnames: &[&str]) -> Result<(), ExcelError> {
+    if book.stream.is_some() && book.sheets.iter().map(|s| s.name.as_str()).ne(sheetnames.iter().copied()) {
+        return Err(ExcelError::ConstantMemory("sheets can't be reordered"));
+    }
+    let mut sheets = Vec::with_capacity(book.sheets.len());
+    for name in sheetnames {
+        let i = book.position(name)?;
+        sheets.push(book.sheets.remove(i));
+    }
+    book.sheets = sheets;
+    Ok(())
+}
+
+// write the workbook to disk
+pub fn save_creater(book: &mut Book, path: &str) -> Result<(), ExcelError> {
+    if let Some(ref mut stream) = book.stream {
+        // styled rows and cells below the last written row still have to be written
+        for (i, sheet) in book.sheets.iter_mut().enumerate() {
+            stream.write_styles(i, sheet, MAX_ROWS, &book.styles)?;
+        }
+        return stream.workbook.close();
+    }
+    let mut wb = lxw::Workbook::new(path, false)?;
+    let mut formats = Formats::default();
+    for sheet in &book.sheets {
+        let i = wb.add_worksheet(&sheet.name)?;
+        for (first, last, style) in column_runs(&sheet.cols) {
+            let format = formats.get(&mut wb, &book.styles, (Some(style), None))?;
+            wb.set_column(i, first, last, format)?;
+        }
+        for (&r, &style) in &sheet.rows {
+            let format = formats.get(&mut wb, &book.styles, (Some(style), None))?;
+            wb.set_row(i, r, format)?;
+        }
+        for (&(r, c), value) in &sheet.cells {
+            let key = (sheet.style_at(r, c), value.value().and_then(DateKind::of));
+            let format = formats.get(&mut wb, &book.styles, key)?;
+            write_cell(&mut wb, i, r, c, value, format)?;
+        }
+        for (&(r, c), &style) in sheet.styles.iter().filter(|(at, _)| !sheet.cells.contains_key(at)) {
+            let format = formats.get(&mut wb, &book.styles, (Some(style), None))?;
+            wb.write_blank(i, r, c, format)?;
+        }
+    }
+    wb.close()
+}
+
+// the styled columns, as runs of neighbours with the same style
+fn column_runs(cols: &BTreeMap<u16, usize>) -> Vec<(u16, u16, usize)> {
+    let mut runs: Vec<(u16, u16, usize)> = Vec::new();
+    for (&c, &style) in cols {
+        match runs.last_mut() {
+            Some(run) if run.1 + 1 == c && run.2 == style => run.1 = c,
+            _ => runs.push((c, c, style)),
+        }
+    }
+    runs
+}
+
+impl Stream {
+    // write a data matrix with its top-left cell at (row, col) into sheet `index`, below
+    // every row written so far. Styled cells of the written rows are included, even without
+    // a value, and so are those of the rows skipped over, as are row formats.
+    fn write_rows(&mut self,
+            index: usize,
+            sheet: &mut Sheet,
+            row: u32,
+            col: u32,
+            data: &[Vec<CellInput>],
+            styles: &[CellStyle]
+        )
+        -> Result<(), ExcelError>
+    {
+        if row < sheet.next_row && !data.is_empty() {
+            return Err(ExcelError::RowFlushed(row));
+        }
+        let arrays = array_ranges(row, col, data)?;
+        for (i, values) in data.iter().enumerate() {
+            let r = row as usize + i;
+            let last = col as usize + values.len().saturating_sub(1);
+            if r >= MAX_ROWS as usize || last >= MAX_COLS as usize {
+                return Err(ExcelError::AddressOutOfRange { row: r, col: last });
+            }
+            let r = r as u32;
+            self.write_styles(index, sheet, r, styles)?;
+            if let Some(&style) = sheet.rows.get(&r) {
+                let format = self.formats.get(&mut self.workbook, styles, (Some(style), None))?;
+                self.workbook.set_row(index, r, format)?;
+            }
+            let mut cells: BTreeMap<u16, (Option<&CellInput>, Option<usize>)> = BTreeMap::new();
+            for (j, value) in values.iter().enumerate().filter(|(_, value)| !value.is_empty()) {
+                let at = Address::new(r, col + j as u32);
+                if arrays.iter().any(|range| range.contains(at) && range.start != at) {
+                    continue;
+                }
+                cells.entry(at.col as u16).or_default().0 = Some(value);
+            }
+            for (&(_, c), &style) in sheet.styles.range((r, 0)..=(r, u16::MAX)) {
+                cells.entry(c).or_default().1 = Some(style);
+            }
+            for (c, (input, style)) in cells {
+                let kind = input.and_then(CellInput::value).and_then(DateKind::of);
+                let style = style.or_else(|| sheet.style_at(r, c));
+                let format = self.formats.get(&mut self.workbook, styles, (style, kind))?;
+                match input {
+                    Some(input) => write_cell(&mut self.workbook, index, r, c, input, format)?,
+                    None => self.workbook.write_blank(index, r, c, format)?,
+                }
+            }
+            sheet.next_row = r + 1;
+        }
+        sheet.styles = sheet.styles.split_off(&(sheet.next_row, 0));
+        sheet.rows = sheet.rows.split_off(&sheet.next_row);
+        Ok(())
+    }
+
+    // write the row formats and styled cells still kept for the rows before `until`
+    fn write_styles(&mut self, index: usize, sheet: &mut Sheet, until: u32, styles: &[CellStyle])
+        -> Result<(), ExcelError>
+    {
+        let rest = sheet.rows.split_off(&until);
+        let mut rows = mem::replace(&mut sheet.rows, rest).into_iter().peekable();
+        let rest = sheet.styles.split_off(&(until, 0));
+        for ((r, c), style) in mem::replace(&mut sheet.styles, rest) {
+            // a row format has to come before the cells of its row
+            while let Some((row, style)) = rows.next_if(|&(row, _)| row <= r) {
+                let format = self.formats.get(&mut self.workbook, styles, (Some(style), None))?;
+                self.workbook.set_row(index, row, format)?;
+            }
+            let format = self.formats.get(&mut self.workbook, styles, (Some(style), None))?;
+            self.workbook.write_blank(index, r, c, format)?;
+        }
+        for (row, style) in rows {
+            let format = self.formats.get(&mut self.workbook, styles, (Some(style), None))?;
+            self.workbook.set_row(index, row, format)?;
+        }
+        Ok(())
+    }
+}
+
+// index into Book::styles and the kind of date in the cell
+type FormatKey = (Option<usize>, Option<DateKind>);
+
+// libxlsxwriter formats by FormatKey, made as they are needed
+#[derive(Default)]
+struct Formats(HashMap<FormatKey, FormatId>);
+
+impl Formats {
+    // the cell's style, showing dates in their default format unless it has a number format
+    fn get(&mut self, wb: &mut lxw::Workbook, styles: &[CellStyle], key: FormatKey)
+        -> Result<Option<FormatId>, ExcelError>
+    {
+        if key == (None, None) {
+            return Ok(None);
+        }
+        if let Some(&id) = self.0.get(&key) {
+            return Ok(Some(id));
+        }
+        let (style, kind) = key;
+        let style = style.map(|i| &styles[i]);
+        let mut format = wb.add_format()?;
+        if let Some(style) = style {
+            style.apply(&mut format);
+        }
+        if let Some(kind) = kind.filter(|_| style.is_none_or(|style| style.num_format.is_none())) {
+            format.set_num_format(kind.format_code());
+        }
+        let id = format.id();
+        self.0.insert(key, id);
+        Ok(Some(id))
+    }
+}
+
+fn write_cell(wb: &mut lxw::Workbook, sheet: usize, r: u32, c: u16, input: &CellInput, format: Option<FormatId>)
+    -> Result<(), ExcelError>
+{
+    let formula = match input.formula_text() {
+        Some(formula) => format!("={}", formula),
+        None => return write_value(wb, sheet, r, c, input.value().unwrap_or(&Data::Empty), format),
+    };
+    input.check_finite(Address::new(r, u32::from(c)))?;
+    // libxlsxwriter can only store a numeric result; other ones are left to recalculation
+    let cached = match input.value() {
+        Some(Data::Int(i)) => Some(*i as f64),
+        Some(Data::Float(f)) => Some(*f),
+        Some(Data::DateTime(d)) => Some(d.as_f64()),
+        _ => None,
+    };
+    match input.array_range(r as usize, usize::from(c))? {
+        Some(range) => {
+            let last = (range.end.row, range.end.col as u16);
+            // libxlsxwriter strips "{=...}" from array formulas, not a bare "="
+            wb.write_array_formula(sheet, (r, c), last, &format!("{{{}}}", formula), format)
+        }
+        None => wb.write_formula(sheet, r, c, &formula, cached, format),
+    }
+}
+
+fn write_value(wb: &mut lxw::Workbook, sheet: usize, r: u32, c: u16, value: &Data, format: Option<FormatId>)
+    -> Result<(), ExcelError>
+{
+    match *value {
+        Data::Empty => Ok(()),
+        Data::Int(i) => wb.write_number(sheet, r, c, i as f64, format),
+        Data::Float(f) if !f.is_finite() => Err(ExcelError::NonFiniteNumber { address: Address::new(r, u32::from(c)), value: f }),
+        Data::Float(f) => wb.write_number(sheet, r, c, f, format),
+        Data::DateTime(ref d) => wb.write_number(sheet, r, c, d.as_f64(), format),
+        Data::Bool(b) => wb.write_boolean(sheet, r, c, b, format),
+        Data::String(ref s) | Data::DateTimeIso(ref s) | Data::DurationIso(ref s) => {
+            wb.write_string(sheet, r, c, s, format)
+        }
+        Data::Error(ref e) => wb.write_formula(sheet, r, c, &format!("={}", e), None, format),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::Read;
+
+    use calamine::Reader;
+    use zip::ZipArchive;
+
+    use super::*;
+    use super::super::reader::open_reader;
+    use super::super::style::Align;
+
+    fn temp_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("excelhandler-{}-{}.xlsx", std::process::id(), name));
+        let _ = std::fs::remove_file(&path);
+        path.to_string_lossy().into_owned()
+    }
+
+    fn part(path: &str, name: &str) -> String {
+        let mut archive = ZipArchive::new(File::open(path).unwrap()).unwrap();
+        let mut xml = String::new();
+        archive.by_name(name).unwrap().read_to_string(&mut xml).unwrap();
+        xml
+    }
+
+    fn report(book: &mut Book) {
+        let header = [CellInput::from(Data::String("n".to_string())), CellInput::from(Data::String("text".to_string()))];
+        append_row_creater(book, "Report", &header, Some(&CellStyle::new().bold())).unwrap();
+        for i in 0..3 {
+            append_row_creater(book, "Report", &[CellInput::from(Data::Int(i))], None).unwrap();
+        }
+    }
+
+    #[test]
+    fn constant_memory_sheets_are_written_in_row_order() {
+        let path = temp_path("stream");
+        let mut book = Book::constant_memory(&path).unwrap();
+        report(&mut book);
+        let late = [vec![CellInput::from(Data::Int(9))]];
+        assert!(matches!(set_range_values_creater(&mut book, "Report", 2, 0, &late, None), Err(ExcelError::RowFlushed(2))));
+        assert!(matches!(rename_sheet_creater(&mut book, "Report", "Other"), Err(ExcelError::ConstantMemory(_))));
+        assert!(matches!(add_sheet_creater(&mut book, "First", 0), Err(ExcelError::ConstantMemory(_))));
+        add_sheet_creater(&mut book, "Last", 1).unwrap();
+        reorder_sheets_creater(&mut book, &["Report", "Last"]).unwrap();
+        save_creater(&mut book, &path).unwrap();
+
+        // libxlsxwriter only writes strings inline in constant_memory mode
+        assert!(part(&path, "xl/worksheets/sheet1.xml").contains(r#"t="inlineStr""#));
+        let mut reader = open_reader(&path).unwrap();
+        assert_eq!(reader.sheet_names(), ["Report", "Last"]);
+        let range = reader.worksheet_range("Report").unwrap();
+        assert_eq!(range.get_value((0, 1)), Some(&Data::String("text".to_string())));
+        assert_eq!(range.get_value((3, 0)), Some(&Data::Float(2.0)));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn constant_memory_rows_keep_the_styles_of_rows_skipped_over() {
+        let path = temp_path("gap");
+        let mut book = Book::constant_memory(&path).unwrap();
+        let fill = CellStyle::new().fill(0xFFFF00);
+        set_style_creater(&mut book, "Report", &"A5:B5".parse().unwrap(), &fill).unwrap();
+        set_style_creater(&mut book, "Report", &"A12".parse().unwrap(), &fill).unwrap();
+        let data = [vec![CellInput::from(Data::Int(1))]];
+        set_range_values_creater(&mut book, "Report", 9, 0, &data, None).unwrap();
+        save_creater(&mut book, &path).unwrap();
+
+        let sheet = part(&path, "xl/worksheets/sheet1.xml");
+        let (a5, b5, a10, a12) = (sheet.find(r#"<c r="A5" s="1"/>"#), sheet.find(r#"<c r="B5" s="1"/>"#),
+                                  sheet.find(r#"<c r="A10""#), sheet.find(r#"<c r="A12" s="1"/>"#));
+        assert!(a5.is_some() && b5.is_some() && a12.is_some(), "{}", sheet);
+        assert!(a5 < b5 && b5 < a10 && a10 < a12, "{}", sheet);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn in_memory_books_are_written_on_save() {
+        let path = temp_path("in-memory");
+        let mut book = Book::default();
+        report(&mut book);
+        set_style_creater(&mut book, "Report", &"B1".parse().unwrap(), &CellStyle::new().align(Align::Center)).unwrap();
+        rename_sheet_creater(&mut book, "Report", "Renamed").unwrap();
+        save_creater(&mut book, &path).unwrap();
+
+        assert!(!part(&path, "xl/worksheets/sheet1.xml").contains(r#"t="inlineStr""#));
+        assert!(part(&path, "xl/styles.xml").contains(r#"<alignment horizontal="center"/>"#));
+        let mut reader = open_reader(&path).unwrap();
+        let range = reader.worksheet_range("Renamed").unwrap();
+        assert_eq!(range.get_value((0, 1)), Some(&Data::String("text".to_string())));
+        assert_eq!(range.get_value((3, 0)), Some(&Data::Float(2.0)));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn numbers_that_are_not_finite_are_rejected() {
+        let mut book = Book::default();
+        report(&mut book);
+        let nan = [vec![CellInput::from(Data::Float(f64::NAN))]];
+        let result = set_range_values_creater(&mut book, "Report", 1, 1, &nan, None);
+        assert!(matches!(result, Err(ExcelError::NonFiniteNumber { address, .. }) if address == Address::new(1, 1)));
+        let cached = [vec![CellInput::formula("1/0").with_cached(Data::Float(f64::INFINITY))]];
+        let result = set_range_values_creater(&mut book, "Report", 1, 1, &cached, None);
+        assert!(matches!(result, Err(ExcelError::NonFiniteNumber { .. })));
+        assert!(!book.sheets[0].cells.contains_key(&(1, 1)));
+
+        let path = temp_path("not-finite");
+        let mut stream = Book::constant_memory(&path).unwrap();
+        let row = [CellInput::from(Data::Float(f64::NEG_INFINITY))];
+        let result = append_row_creater(&mut stream, "Report", &row, None);
+        assert!(matches!(result, Err(ExcelError::NonFiniteNumber { address, .. }) if address == Address::new(0, 0)));
+        drop(stream);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn array_formulas_leave_the_cells_they_cover_empty() {
+        let mut book = Book::default();
+        report(&mut book);
+        let range: CellRange = "A2:B3".parse().unwrap();
+        let data = [vec![CellInput::array_formula("A1:A2", &range)]];
+        set_range_values_creater(&mut book, "Report", 1, 0, &data, None).unwrap();
+        let cells: Vec<_> = book.sheets[0].cells.keys().copied().collect();
+        assert_eq!(cells, vec![(0, 0), (0, 1), (1, 0), (3, 0)]);
+
+        let path = temp_path("array");
+        let mut stream = Book::constant_memory(&path).unwrap();
+        let data = [vec![CellInput::array_formula("A1:A2", &range), CellInput::from(Data::Int(1))]];
+        set_range_values_creater(&mut stream, "Report", 1, 0, &data, None).unwrap();
+        let data = [vec![CellInput::array_formula("A1:A2", &range)]];
+        let result = set_range_values_creater(&mut stream, "Report", MAX_ROWS - 1, 0, &data, None);
+        assert!(matches!(result, Err(ExcelError::AddressOutOfRange { .. })));
+        save_creater(&mut stream, &path).unwrap();
+        let sheet = part(&path, "xl/worksheets/sheet1.xml");
+        assert!(sheet.contains(r#"<f t="array" ref="A2:B3">A1:A2</f>"#), "{}", sheet);
+        assert!(!sheet.contains(r#"<c r="B2""#), "{}", sheet);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn array_formulas_are_only_replaced_from_their_first_cell() {
+        let mut book = Book::default();
+        let range: CellRange = "A2:B3".parse().unwrap();
+        let data = [vec![CellInput::array_formula("C2:D3", &range)]];
+        set_range_values_creater(&mut book, "Report", 1, 0, &data, None).unwrap();
+        let one = [vec![CellInput::from(Data::Int(1))]];
+        let result = set_range_values_creater(&mut book, "Report", 2, 1, &one, None);
+        assert!(matches!(result, Err(ExcelError::ArrayFormulaPart(ref r)) if r.to_string() == "Report!A2:B3"));
+        let over = [vec![CellInput::array_formula("C2:D3", &"B3:C3".parse().unwrap())]];
+        assert!(set_range_values_creater(&mut book, "Report", 2, 1, &over, None).is_err());
+        // blanks around it are fine
+        set_range_values_creater(&mut book, "Report", 2, 1, &[vec![CellInput::from(Data::Empty)]], None).unwrap();
+
+        set_range_values_creater(&mut book, "Report", 1, 0, &one, None).unwrap();
+        set_range_values_creater(&mut book, "Report", 2, 1, &one, None).unwrap();
+        assert!(book.sheets[0].arrays.is_empty());
+
+        let path = temp_path("array-stream");
+        let mut stream = Book::constant_memory(&path).unwrap();
+        set_range_values_creater(&mut stream, "Report", 1, 0, &data, None).unwrap();
+        let result = set_range_values_creater(&mut stream, "Report", 2, 1, &one, None);
+        assert!(matches!(result, Err(ExcelError::ArrayFormulaPart(_))));
+        set_range_values_creater(&mut stream, "Report", 2, 2, &one, None).unwrap();
+        save_creater(&mut stream, &path).unwrap();
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn whole_rows_and_columns_get_row_and_column_formats() {
+        let path = temp_path("row-col");
+        let mut book = Book::default();
+        report(&mut book);
+        let fill = CellStyle::new().fill(0xFFFF00);
+        set_style_creater(&mut book, "Report", &"B2".parse().unwrap(), &CellStyle::new().bold()).unwrap();
+        set_style_creater(&mut book, "Report", &"B:C".parse().unwrap(), &fill).unwrap();
+        set_style_creater(&mut book, "Report", &"3:3".parse().unwrap(), &CellStyle::new().italic()).unwrap();
+        assert!(book.sheets[0].styles.keys().all(|&(r, c)| r == 0 && c == 0));
+        assert_eq!(book.sheets[0].style_at(1, 1), Some(book.style_index(&fill)));
+        save_creater(&mut book, &path).unwrap();
+
+        let sheet = part(&path, "xl/worksheets/sheet1.xml");
+        assert!(sheet.contains(r#"<col min="2" max="3" width="9.140625" style="1"/>"#), "{}", sheet);
+        assert!(sheet.contains(r#"<row r="3" spans="1:2" s="3" customFormat="1"><c r="A3" s="3">"#), "{}", sheet);
+        let _ = std::fs::remove_file(&path);
+
+        let path = temp_path("row-col-stream");
+        let mut book = Book::constant_memory(&path).unwrap();
+        set_style_creater(&mut book, "Report", &"C:C".parse().unwrap(), &fill).unwrap();
+        set_style_creater(&mut book, "Report", &"2:3".parse().unwrap(), &CellStyle::new().italic()).unwrap();
+        set_style_creater(&mut book, "Report", &"A6:XFD6".parse().unwrap(), &CellStyle::new().bold()).unwrap();
+        let data = [vec![CellInput::from(Data::Int(1))]];
+        set_range_values_creater(&mut book, "Report", 2, 0, &data, None).unwrap();
+        save_creater(&mut book, &path).unwrap();
+
+        let sheet = part(&path, "xl/worksheets/sheet1.xml");
+        assert!(sheet.contains(r#"<col min="3" max="3""#), "{}", sheet);
+        let (r2, r3, r6) = (sheet.find(r#"<row r="2" s="1""#), sheet.find(r#"<row r="3" s="1""#), sheet.find(r#"<row r="6" s="2""#));
+        assert!(r2.is_some() && r3.is_some() && r6.is_some(), "{}", sheet);
+        assert!(r2 < r3 && r3 < r6, "{}", sheet);
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,195 @@
+use std::str::FromStr;
+
+use calamine::{Data, ExcelDateTime, ExcelDateTimeType};
+use chrono::{Duration, NaiveDate, NaiveDateTime, NaiveTime, Timelike};
+
+const MS_PER_DAY: f64 = 86_400_000.0;
+
+
+// How a workbook counts days. Excel for Windows uses 1900, where serial 1 is 1900-01-01
+// and serial 60 is the 1900-02-29 that never was (Lotus 1-2-3 treated 1900 as a leap year).
+// Old Mac workbooks use 1904, where serial 0 is 1904-01-01.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DateSystem {
+    #[default]
+    V1900,
+    V1904,
+}
+
+impl DateSystem {
+    // the serial number of a date and time, None before the first day of the system or after 9999
+    pub fn to_serial(self, datetime: NaiveDateTime) -> Option<f64> {
+        let date = datetime.date();
+        let (epoch, first) = match self {
+            DateSystem::V1900 => (ymd(1899, 12, 30), ymd(1899, 12, 31)), // serial 0 is the day before 1900-01-01
+            DateSystem::V1904 => (ymd(1904, 1, 1), ymd(1904, 1, 1)),
+        };
+        if date < first || date > ymd(9999, 12, 31) {
+            return None;
+        }
+        let mut days = (date - epoch).num_days() as f64;
+        if self == DateSystem::V1900 && date < ymd(1900, 3, 1) {
+            days -= 1.0; // before the phantom leap day
+        }
+        Some(days + time_to_serial(datetime.time()))
+    }
+
+    // the date and time of a serial number, None for the phantom 1900-02-29
+    pub fn from_serial(self, serial: f64) -> Option<NaiveDateTime> {
+        if !serial.is_finite() || serial < 0.0 {
+            return None;
+        }
+        let epoch = match self {
+            DateSystem::V1900 if serial < 60.0 => ymd(1899, 12, 31),
+            DateSystem::V1900 if serial < 61.0 => return None,
+            DateSystem::V1900 => ymd(1899, 12, 30),
+            DateSystem::V1904 => ymd(1904, 1, 1),
+        };
+        let ms = (serial * MS_PER_DAY).round() as i64;
+        epoch.and_time(NaiveTime::MIN).checked_add_signed(Duration::milliseconds(ms))
+    }
+}
+
+// Reading cells as chrono values. Date-formatted cells carry their workbook's date system;
+// plain numbers are read as serials of `system`, see ExcelHandle::date_system.
+pub trait DateValue {
+    fn to_datetime(&self, system: DateSystem) -> Option<NaiveDateTime>;
+
+    fn to_date(&self, system: DateSystem) -> Option<NaiveDate> {
+        self.to_datetime(system).map(|datetime| datetime.date())
+    }
+
+    fn to_duration(&self) -> Option<Duration>;
+}
+
+impl DateValue for Data {
+    fn to_datetime(&self, system: DateSystem) -> Option<NaiveDateTime> {
+        match *self {
+            Data::DateTime(ref d) if d.is_datetime() => {
+                // already adjusted for the workbook's date system, and Feb 29 1900 fails as it should
+                let (y, m, d, h, min, s, ms) = d.to_ymd_hms_milli();
+                NaiveDate::from_ymd_opt(y.into(), m.into(), d.into())?
+                    .and_hms_milli_opt(h.into(), min.into(), s.into(), ms.into())
+            }
+            Data::Int(i) => system.from_serial(i as f64),
+            Data::Float(f) => system.from_serial(f),
+            Data::DateTimeIso(ref s) => NaiveDateTime::from_str(s).ok()
+                .or_else(|| NaiveDate::from_str(s).ok().map(|date| date.and_time(NaiveTime::MIN))),
+            _ => None,
+        }
+    }
+
+    fn to_duration(&self) -> Option<Duration> {
+        match *self {
+            Data::DateTime(ref d) => Some(serial_to_duration(d.as_f64())),
+            Data::Int(i) => Some(serial_to_duration(i as f64)),
+            Data::Float(f) => Some(serial_to_duration(f)),
+            // ods durations like PT36H12M30S
+            Data::DurationIso(ref s) => {
+                let (h, rest) = s.strip_prefix("PT")?.split_once('H')?;
+                let (m, rest) = rest.split_once('M')?;
+                let seconds = rest.strip_suffix('S')?.parse::<f64>().ok()?;
+                let minutes = h.parse::<i64>().ok()? * 60 + m.parse::<i64>().ok()?;
+                Some(Duration::minutes(minutes) + Duration::milliseconds((seconds * 1000.0).round() as i64))
+            }
+            _ => None,
+        }
+    }
+}
+
+// How a date cell is shown when no number format is given
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DateKind {
+    DateTime,
+    Date, // no time of day
+    Duration,
+}
+
+impl DateKind {
+    pub fn of(value: &Data) -> Option<Self> {
+        match *value {
+            Data::DateTime(ref d) if d.is_duration() => Some(DateKind::Duration),
+            Data::DateTime(ref d) if d.as_f64().fract() == 0.0 => Some(DateKind::Date),
+            Data::DateTime(_) => Some(DateKind::DateTime),
+            _ => None,
+        }
+    }
+
+    // Excel's built-in number format
+    pub fn format_id(self) -> u32 {
+        match self {
+            DateKind::DateTime => 22,
+            DateKind::Date => 14,
+            DateKind::Duration => 46,
+        }
+    }
+
+    // what format_id shows, as a format code for libxlsxwriter
+    pub fn format_code(self) -> &'static str {
+        match self {
+            DateKind::DateTime => "m/d/yyyy h:mm",
+            DateKind::Date => "m/d/yyyy",
+            DateKind::Duration => "[h]:mm:ss",
+        }
+    }
+}
+
+// a date cell of `system`, None when the date can't be stored
+pub fn datetime_value(datetime: NaiveDateTime, system: DateSystem) -> Option<Data> {
+    let serial = system.to_serial(datetime)?;
+    Some(Data::DateTime(ExcelDateTime::new(serial, ExcelDateTimeType::DateTime, system == DateSystem::V1904)))
+}
+
+pub fn duration_value(duration: Duration) -> Data {
+    let serial = duration.num_milliseconds() as f64 / MS_PER_DAY;
+    Data::DateTime(ExcelDateTime::new(serial, ExcelDateTimeType::TimeDelta, false))
+}
+
+fn serial_to_duration(serial: f64) -> Duration {
+    Duration::milliseconds((serial * MS_PER_DAY).round() as i64)
+}
+
+fn time_to_serial(time: NaiveTime) -> f64 {
+    let ms = time.num_seconds_from_midnight() as f64 * 1000.0 + (time.nanosecond() / 1_000_000) as f64;
+    ms / MS_PER_DAY
+}
+
+fn ymd(year: i32, month: u32, day: u32) -> NaiveDate {
+    NaiveDate::from_ymd_opt(year, month, day).unwrap_or_default()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(year: i32, month: u32, day: u32) -> NaiveDateTime {
+        ymd(year, month, day).and_time(NaiveTime::MIN)
+    }
+
+    #[test]
+    fn serials_around_the_phantom_leap_day() {
+        let system = DateSystem::V1900;
+        assert_eq!(system.to_serial(at(1900, 2, 28)), Some(59.0));
+        assert_eq!(system.to_serial(at(1900, 3, 1)), Some(61.0));
+        assert_eq!(system.to_serial(at(1900, 1, 1)), Some(1.0));
+        assert_eq!(system.to_serial(at(1899, 12, 31)), Some(0.0));
+        assert_eq!(system.to_serial(at(1899, 12, 30)), None);
+        assert_eq!(system.from_serial(59.0), Some(at(1900, 2, 28)));
+        assert_eq!(system.from_serial(60.0), None);
+        assert_eq!(system.from_serial(61.0), Some(at(1900, 3, 1)));
+        assert_eq!(system.from_serial(0.0), Some(at(1899, 12, 31)));
+        assert_eq!(system.from_serial(45351.5), Some(at(2024, 2, 29) + Duration::hours(12)));
+    }
+
+    #[test]
+    fn serials_of_the_1904_system() {
+        let system = DateSystem::V1904;
+        assert_eq!(system.to_serial(at(1904, 1, 1)), Some(0.0));
+        assert_eq!(system.to_serial(at(1903, 12, 31)), None);
+        assert_eq!(system.from_serial(0.0), Some(at(1904, 1, 1)));
+        // 1462 days apart from 1900 serials
+        assert_eq!(system.to_serial(at(2024, 2, 29)), Some(45351.0 - 1462.0));
+        assert_eq!(system.from_serial(45351.0 - 1462.0), Some(at(2024, 2, 29)));
+    }
+}
@@ -0,0 +1,326 @@
+use std::error;
+use std::fmt;
+
+use calamine::{Data, Range};
+use serde::de::{self, DeserializeOwned, DeserializeSeed, IntoDeserializer, MapAccess, Visitor};
+use serde::forward_to_deserialize_any;
+
+use super::address::Address;
+use super::error::ExcelError;
+
+
+// Rows below `header_row` as structs, skipping rows with nothing under the headers.
+// Repeated headers keep their first column.
+pub fn deserialize_rows<T>(range: &Range<Data>, header_row: u32) -> Result<Vec<T>, ExcelError>
+    where
+        T: DeserializeOwned,
+{
+    let end = match range.end() {
+        Some(end) if end.0 > header_row => end,
+        _ => return Ok(Vec::new()),
+    };
+    let start_col = range.start().map_or(0, |start| start.1);
+    let mut headers: Vec<(String, u32)> = Vec::new();
+    for col in start_col..=end.1 {
+        let name = match range.get_value((header_row, col)) {
+            Some(Data::Empty) | None => continue,
+            Some(value) => value.to_string().trim().to_string(),
+        };
+        if !name.is_empty() && !headers.iter().any(|(h, _)| *h == name) {
+            headers.push((name, col));
+        }
+    }
+
+    let mut rows = Vec::new();
+    for row in header_row + 1..=end.0 {
+        if headers.iter().all(|&(_, col)| is_blank(range.get_value((row, col)))) {
+            continue;
+        }
+        let first = Address::new(row, headers.first().map_or(start_col, |&(_, col)| col));
+        let deserializer = RowDeserializer { range, headers: &headers, row, next: 0 };
+        rows.push(T::deserialize(deserializer).map_err(|e| e.into_excel_error(first))?);
+    }
+    Ok(rows)
+}
+
+// A failed conversion, located at the cell it happened in where known
+#[derive(Debug)]
+struct DeError {
+    address: Option<Address>,
+    message: String,
+}
+
+impl DeError {
+    fn at(mut self, address: Address) -> Self {
+        self.address.get_or_insert(address);
+        self
+    }
+
+    fn into_excel_error(self, row_start: Address) -> ExcelError {
+        ExcelError::Deserialize { address: self.address.unwrap_or(row_start), message: self.message }
+    }
+}
+
+impl fmt::Display for DeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl error::Error for DeError {}
+
+impl de::Error for DeError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        DeError { address: None, message: msg.to_string() }
+    }
+}
+
+fn is_blank(value: Option<&Data>) -> bool {
+    match value {
+        None | Some(Data::Empty) => true,
+        Some(Data::String(s)) => s.trim().is_empty(),
+        _ => false,
+    }
+}
+
+
+// A data row seen as a map from header to cell
+struct RowDeserializer<'a> {
+    range: &'a Range<Data>,
+    headers: &'a [(String, u32)],
+    row: u32,
+    next: usize, // header whose value comes next
+}
+
+impl<'de, 'a> de::Deserializer<'de> for RowDeserializer<'a> {
+    type Error = DeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        visitor.visit_map(self)
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+impl<'de, 'a> MapAccess<'de> for RowDeserializer<'a> {
+    type Error = DeError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>, DeError> {
+        match self.headers.get(self.next) {
+            Some((name, _)) => seed.deserialize(name.as_str().into_deserializer()).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, DeError> {
+        let col = self.headers[self.next].1;
+        self.next += 1;
+        let address = Address::new(self.row, col);
+        let value = self.range.get_value((self.row, col)).unwrap_or(&Data::Empty);
+        seed.deserialize(CellDeserializer { value }).map_err(|e| e.at(address))
+    }
+}
+
+
+// One cell, converting between numbers and text as the field needs
+struct CellDeserializer<'a> {
+    value: &'a Data,
+}
+
+impl<'a> CellDeserializer<'a> {
+    fn invalid(&self, expected: &str) -> DeError {
+        de::Error::custom(format!("expected {}, found {:?}", expected, self.value))
+    }
+
+    fn integer(&self) -> Result<i64, DeError> {
+        match *self.value {
+            Data::Int(i) => Ok(i),
+            Data::Float(f) if f.fract() == 0.0 && f.abs() < i64::MAX as f64 => Ok(f as i64),
+            Data::String(ref s) => s.trim().parse().map_err(|_| self.invalid("an integer")),
+            Data::Bool(b) => Ok(b as i64),
+            _ => Err(self.invalid("an integer")),
+        }
+    }
+
+    fn float(&self) -> Result<f64, DeError> {
+        match *self.value {
+            Data::Int(i) => Ok(i as f64),
+            Data::Float(f) => Ok(f),
+            Data::DateTime(ref d) => Ok(d.as_f64()),
+            Data::String(ref s) => s.trim().parse().map_err(|_| self.invalid("a number")),
+            _ => Err(self.invalid("a number")),
+        }
+    }
+}
+
+impl<'de, 'a> de::Deserializer<'de> for CellDeserializer<'a> {
+    type Error = DeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        match *self.value {
+            Data::Empty => visitor.visit_unit(),
+            Data::Int(i) => visitor.visit_i64(i),
+            Data::Float(f) => visitor.visit_f64(f),
+            Data::DateTime(ref d) => visitor.visit_f64(d.as_f64()),
+            Data::Bool(b) => visitor.visit_bool(b),
+            Data::String(ref s) | Data::DateTimeIso(ref s) | Data::DurationIso(ref s) => visitor.visit_str(s),
+            Data::Error(ref e) => Err(de::Error::custom(format!("cell contains the error {}", e))),
+        }
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        match *self.value {
+            Data::Bool(b) => visitor.visit_bool(b),
+            Data::Int(i) => visitor.visit_bool(i != 0),
+            Data::Float(f) => visitor.visit_bool(f != 0.0),
+            Data::String(ref s) => match s.trim().to_lowercase().as_str() {
+                "true" | "1" => visitor.visit_bool(true),
+                "false" | "0" => visitor.visit_bool(false),
+                _ => Err(self.invalid("a boolean")),
+            },
+            _ => Err(self.invalid("a boolean")),
+        }
+    }
+
+    fn deserialize_i64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        visitor.visit_i64(self.integer()?)
+    }
+
+    fn deserialize_i8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        self.deserialize_i64(visitor)
+    }
+
+    fn deserialize_i16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        self.deserialize_i64(visitor)
+    }
+
+    fn deserialize_i32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        self.deserialize_i64(visitor)
+    }
+
+    // the visitor rejects negative values and ones too large for the field
+    fn deserialize_u64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        self.deserialize_i64(visitor)
+    }
+
+    fn deserialize_u8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        self.deserialize_i64(visitor)
+    }
+
+    fn deserialize_u16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        self.deserialize_i64(visitor)
+    }
+
+    fn deserialize_u32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        self.deserialize_i64(visitor)
+    }
+
+    fn deserialize_f64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        visitor.visit_f64(self.float()?)
+    }
+
+    fn deserialize_f32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        self.deserialize_f64(visitor)
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        match *self.value {
+            Data::Empty => visitor.visit_str(""),
+            Data::Error(_) => self.deserialize_any(visitor),
+            ref value => visitor.visit_string(value.to_string()),
+        }
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_char<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        self.deserialize_str(visitor)
+    }
+
+    // blank cells, including text of only spaces
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        if is_blank(Some(self.value)) {
+            visitor.visit_none()
+        } else {
+            visitor.visit_some(self)
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V)
+        -> Result<V::Value, DeError>
+    {
+        visitor.visit_newtype_struct(self)
+    }
+
+    // unit variants by name
+    fn deserialize_enum<V: Visitor<'de>>(self, _name: &'static str, _variants: &'static [&'static str], visitor: V)
+        -> Result<V::Value, DeError>
+    {
+        match *self.value {
+            Data::String(ref s) => visitor.visit_enum(s.trim().into_deserializer()),
+            _ => Err(self.invalid("text naming a variant")),
+        }
+    }
+
+    // columns without a field, where even an error cell is fine
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        visitor.visit_unit()
+    }
+
+    forward_to_deserialize_any! {
+        i128 u128 bytes byte_buf unit unit_struct seq tuple tuple_struct map struct identifier
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use calamine::CellErrorType;
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Item {
+        name: String,
+        price: f64,
+    }
+
+    fn range(rows: &[Vec<Data>]) -> Range<Data> {
+        let mut range = Range::new((0, 0), (rows.len() as u32 - 1, rows[0].len() as u32 - 1));
+        for (r, row) in rows.iter().enumerate() {
+            for (c, value) in row.iter().enumerate() {
+                range.set_value((r as u32, c as u32), value.clone());
+            }
+        }
+        range
+    }
+
+    #[test]
+    fn error_cells_in_columns_without_a_field_are_skipped() {
+        let range = range(&[
+            vec![Data::from("name"), Data::from("note"), Data::from("price")],
+            vec![Data::from("pen"), Data::Error(CellErrorType::Div0), Data::Float(1.5)],
+        ]);
+        let items: Vec<Item> = deserialize_rows(&range, 0).unwrap();
+        assert_eq!(items, vec![Item { name: "pen".to_string(), price: 1.5 }]);
+    }
+
+    #[test]
+    fn error_cells_under_a_field_fail_at_their_address() {
+        let range = range(&[
+            vec![Data::from("name"), Data::from("price")],
+            vec![Data::from("pen"), Data::Error(CellErrorType::Div0)],
+        ]);
+        match deserialize_rows::<Item>(&range, 0) {
+            Err(ExcelError::Deserialize { address, .. }) => assert_eq!(address, Address::new(1, 1)),
+            other => panic!("{:?}", other),
+        }
+    }
+}
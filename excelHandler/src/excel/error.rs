@@ -0,0 +1,139 @@
+use std::error;
+use std::fmt;
+use std::io;
+
+use super::address::{Address, CellRange};
+use super::sheetname::SheetNameError;
+
+
+// Errors returned by ExcelHandle
+#[derive(Debug)]
+pub enum ExcelError {
+    SheetNotFound(String),
+    FileExists(String),
+    ReadOnly, // the workbook format can only be read
+    InvalidSheetName(SheetNameError),
+    SheetIndexOutOfRange(usize),
+    InvalidSheetOrder, // the new order doesn't list every sheet exactly once
+    LastSheet, // a workbook must keep at least one sheet
+    AddressOutOfRange { row: usize, col: usize },
+    NonFiniteNumber { address: Address, value: f64 }, // NaN and infinities can't be stored
+    ArrayFormulaPart(CellRange), // an array formula is only replaced as a whole, from its first cell
+    RowFlushed(u32), // constant-memory sheets are written in row order
+    ConstantMemory(&'static str), // the sheets of a constant-memory workbook are fixed once added
+    InvalidAddress(String), // not A1 notation
+    InvalidPattern(regex::Error),
+    InvalidScan(&'static str), // the scan can't be used by this method
+    StyleNotFound(String), // no style defined under that name
+    AlreadySaved, // styles can only be set before a created workbook is saved
+    DateOutOfRange(String), // before the first day of the date system or after 9999
+    Deserialize { address: Address, message: String }, // a cell doesn't fit its field
+    InvalidPackage(&'static str), // a part of the .xlsx file is missing or malformed
+    Io(io::Error),
+    Parse(calamine::Error),
+    Write(xlsxwriter::XlsxError),
+    Xml(quick_xml::Error),
+    Zip(zip::result::ZipError),
+}
+
+impl fmt::Display for ExcelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExcelError::SheetNotFound(name) => write!(f, "Sheet \"{}\" not found", name),
+            ExcelError::FileExists(path) => write!(f, "File with same name already exists: {}", path),
+            ExcelError::ReadOnly => write!(f, "Workbook can only be read"),
+            ExcelError::InvalidSheetName(e) => write!(f, "Invalid sheet name: {}", e),
+            ExcelError::SheetIndexOutOfRange(i) => write!(f, "Sheet index {} out of range", i),
+            ExcelError::InvalidSheetOrder => write!(f, "Sheet order must list every sheet exactly once"),
+            ExcelError::LastSheet => write!(f, "A workbook must keep at least one sheet"),
+            ExcelError::AddressOutOfRange { row, col } => write!(f, "Cell address ({}, {}) out of range", row, col),
+            ExcelError::NonFiniteNumber { address, value } => write!(f, "Can't store {} in cell {}, numbers must be finite", value, address),
+            ExcelError::ArrayFormulaPart(range) => write!(f, "Can't change part of the array formula in {}", range),
+            ExcelError::RowFlushed(row) => write!(f, "Row {} was already written to the constant-memory sheet", row),
+            ExcelError::ConstantMemory(msg) => write!(f, "Not possible in a constant-memory workbook: {}", msg),
+            ExcelError::InvalidAddress(s) => write!(f, "Invalid cell address: {}", s),
+            ExcelError::InvalidPattern(e) => write!(f, "Invalid pattern: {}", e),
+            ExcelError::InvalidScan(msg) => write!(f, "Invalid scan: {}", msg),
+            ExcelError::StyleNotFound(name) => write!(f, "Style \"{}\" not defined", name),
+            ExcelError::AlreadySaved => write!(f, "Styles can only be set before the workbook is saved"),
+            ExcelError::DateOutOfRange(date) => write!(f, "Date {} can't be stored in the workbook", date),
+            ExcelError::Deserialize { address, message } => write!(f, "Can't read cell {}: {}", address, message),
+            ExcelError::InvalidPackage(msg) => write!(f, "Invalid workbook package: {}", msg),
+            ExcelError::Io(e) => write!(f, "I/O error: {}", e),
+            ExcelError::Parse(e) => write!(f, "Failed to read workbook: {}", e),
+            ExcelError::Write(e) => write!(f, "Failed to write workbook: {}", e),
+            ExcelError::Xml(e) => write!(f, "XML error: {}", e),
+            ExcelError::Zip(e) => write!(f, "Zip error: {}", e),
+        }
+    }
+}
+
+impl error::Error for ExcelError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            ExcelError::InvalidSheetName(e) => Some(e),
+            ExcelError::InvalidPattern(e) => Some(e),
+            ExcelError::Io(e) => Some(e),
+            ExcelError::Parse(e) => Some(e),
+            ExcelError::Write(e) => Some(e),
+            ExcelError::Xml(e) => Some(e),
+            ExcelError::Zip(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<SheetNameError> for ExcelError {
+    fn from(e: SheetNameError) -> Self {
+        ExcelError::InvalidSheetName(e)
+    }
+}
+
+impl From<regex::Error> for ExcelError {
+    fn from(e: regex::Error) -> Self {
+        ExcelError::InvalidPattern(e)
+    }
+}
+
+impl From<io::Error> for ExcelError {
+    fn from(e: io::Error) -> Self {
+        ExcelError::Io(e)
+    }
+}
+
+impl From<calamine::Error> for ExcelError {
+    fn from(e: calamine::Error) -> Self {
+        match e {
+            calamine::Error::Io(e) => ExcelError::Io(e),
+            e => ExcelError::Parse(e),
+        }
+    }
+}
+
+impl From<calamine::XlsxError> for ExcelError {
+    fn from(e: calamine::XlsxError) -> Self {
+        match e {
+            calamine::XlsxError::Io(e) => ExcelError::Io(e),
+            calamine::XlsxError::WorksheetNotFound(name) => ExcelError::SheetNotFound(name),
+            e => ExcelError::Parse(e.into()),
+        }
+    }
+}
+
+impl From<xlsxwriter::XlsxError> for ExcelError {
+    fn from(e: xlsxwriter::XlsxError) -> Self {
+        ExcelError::Write(e)
+    }
+}
+
+impl From<zip::result::ZipError> for ExcelError {
+    fn from(e: zip::result::ZipError) -> Self {
+        ExcelError::Zip(e)
+    }
+}
+
+impl From<quick_xml::Error> for ExcelError {
+    fn from(e: quick_xml::Error) -> Self {
+        ExcelError::Xml(e)
+    }
+}
@@ -0,0 +1,339 @@
+use super::address::{column_name, parse_column, parse_row, MAX_COLS, MAX_ROWS};
+
+
+// A piece of formula text, as far as references to cells and sheets go
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Token<'a> {
+    Other(&'a str), // operators, numbers, strings, function and defined names
+    Sheet(&'a str), // a sheet prefix with its '!': "Sheet1!", "'My sheet'!", "Jan:Mar!"
+    Cell(&'a str), // "B3", "$B$3"
+    Cols(&'a str), // "A:C"
+    Rows(&'a str), // "3:5"
+}
+
+// split A1-style formula text (as stored, without '=') into tokens
+pub fn tokens(formula: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut other = 0; // start of the text not yet in a token
+    let mut i = 0;
+    while let Some(c) = formula[i..].chars().next() {
+        let (end, token) = match c {
+            '"' => {
+                i = quoted_end(formula, i);
+                continue;
+            }
+            '\'' => {
+                let end = quoted_end(formula, i);
+                if !formula[end..].starts_with('!') || formula[i..].starts_with("'[") {
+                    i = end; // a sheet of another workbook
+                    continue;
+                }
+                (end + 1, Token::Sheet(&formula[i..end + 1]))
+            }
+            '[' => {
+                i = bracket_end(formula, i); // another workbook or a structured reference
+                continue;
+            }
+            // error values such as #REF! or #DIV/0!
+            '#' if formula[i + 1..].starts_with(|c: char| c.is_ascii_alphabetic()) => {
+                i += 1 + formula[i + 1..].find(|c: char| !(c.is_ascii_alphanumeric() || "_/!?".contains(c)))
+                    .unwrap_or(formula.len() - i - 1);
+                continue;
+            }
+            c if is_name_char(c) => {
+                let end = word_end(formula, i);
+                let word = &formula[i..end];
+                let after = &formula[end..];
+                if formula[..i].ends_with(']') || after.starts_with('(') {
+                    i = end; // a sheet of another workbook, or a function
+                    continue;
+                }
+                if after.starts_with('!') {
+                    (end + 1, Token::Sheet(&formula[i..end + 1]))
+                } else if let Some(second) = after.strip_prefix(':').filter(|s| s.starts_with(is_name_char)) {
+                    let second_end = end + 1 + word_end(second, 0);
+                    let second = &formula[end + 1..second_end];
+                    if formula[second_end..].starts_with('!') {
+                        (second_end + 1, Token::Sheet(&formula[i..second_end + 1])) // Jan:Mar!A1
+                    } else if column(word).is_some() && column(second).is_some() {
+                        (second_end, Token::Cols(&formula[i..second_end]))
+                    } else if row(word).is_some() && row(second).is_some() {
+                        (second_end, Token::Rows(&formula[i..second_end]))
+                    } else if cell(word).is_some() {
+                        (end, Token::Cell(word))
+                    } else {
+                        i = end;
+                        continue;
+                    }
+                } else if cell(word).is_some() {
+                    (end, Token::Cell(word))
+                } else {
+                    i = end;
+                    continue;
+                }
+            }
+            c => {
+                i += c.len_utf8();
+                continue;
+            }
+        };
+        if other < i {
+            tokens.push(Token::Other(&formula[other..i]));
+        }
+        tokens.push(token);
+        other = end;
+        i = end;
+    }
+    if other < formula.len() {
+        tokens.push(Token::Other(&formula[other..]));
+    }
+    tokens
+}
+
+// The formula of a cell `rows` and `cols` away, as Excel copies formulas:
+// relative references move along, $-fixed parts stay and references moved
+// off the sheet become #REF!
+pub fn shift(formula: &str, rows: i64, cols: i64) -> String {
+    let mut shifted = String::with_capacity(formula.len());
+    for token in tokens(formula) {
+        let moved = match token {
+            Token::Other(text) | Token::Sheet(text) => Some(text.to_string()),
+            Token::Cell(text) => cell(text).and_then(|(col, row)| Some(format!("{}{}", col.shift(cols)?, row.shift(rows)?))),
+            Token::Cols(text) => text.split_once(':').and_then(|(a, b)| {
+                Some(format!("{}:{}", column(a)?.shift(cols)?, column(b)?.shift(cols)?))
+            }),
+            Token::Rows(text) => text.split_once(':').and_then(|(a, b)| {
+                Some(format!("{}:{}", row(a)?.shift(rows)?, row(b)?.shift(rows)?))
+            }),
+        };
+        shifted.push_str(moved.as_deref().unwrap_or("#REF!"));
+    }
+    shifted
+}
+
+// The formula with references to sheet `old` pointing at `new` instead, None when it has none.
+// Sheet names are matched ignoring case, as Excel does.
+pub fn rename_sheet(formula: &str, old: &str, new: &str) -> Option<String> {
+    let old = old.to_lowercase();
+    let mut renamed = String::with_capacity(formula.len());
+    let mut changed = false;
+    for token in tokens(formula) {
+        match token {
+            Token::Sheet(prefix) => {
+                let names = sheet_names(prefix);
+                if names.iter().any(|name| name.to_lowercase() == old) {
+                    let names: Vec<&str> = names.iter()
+                        .map(|name| if name.to_lowercase() == old { new } else { name.as_str() })
+                        .collect();
+                    renamed.push_str(&sheet_prefix(&names));
+                    changed = true;
+                } else {
+                    renamed.push_str(prefix);
+                }
+            }
+            Token::Other(text) | Token::Cell(text) | Token::Cols(text) | Token::Rows(text) => renamed.push_str(text),
+        }
+    }
+    if changed { Some(renamed) } else { None }
+}
+
+// The formula with references to the deleted sheet `name` turned into #REF!, as Excel does,
+// None when it has none. 3-D references naming it (Jan:Mar!) go the same way.
+pub fn remove_sheet(formula: &str, name: &str) -> Option<String> {
+    let name = name.to_lowercase();
+    let tokens = tokens(formula);
+    let mut removed = String::with_capacity(formula.len());
+    let mut changed = false;
+    let mut i = 0;
+    while let Some(&token) = tokens.get(i) {
+        i += 1;
+        match token {
+            Token::Sheet(prefix) if sheet_names(prefix).iter().any(|n| n.to_lowercase() == name) => {
+                removed.push_str("#REF!");
+                changed = true;
+                // the reference behind the prefix: A1, A1:B2, A:C, 3:5 or a defined name
+                match tokens.get(i) {
+                    Some(Token::Cell(_)) => {
+                        i += 1;
+                        if let (Some(Token::Other(":")), Some(Token::Cell(_))) = (tokens.get(i), tokens.get(i + 1)) {
+                            i += 2;
+                        }
+                    }
+                    Some(Token::Cols(_)) | Some(Token::Rows(_)) => i += 1,
+                    Some(Token::Other(text)) => {
+                        removed.push_str(&text[word_end(text, 0)..]);
+                        i += 1;
+                    }
+                    _ => {}
+                }
+            }
+            Token::Other(text) | Token::Sheet(text) | Token::Cell(text) | Token::Cols(text) | Token::Rows(text) => {
+                removed.push_str(text);
+            }
+        }
+    }
+    if changed { Some(removed) } else { None }
+}
+
+// "'Jan ''21':Mar!" -> ["Jan '21", "Mar"]
+pub fn sheet_names(prefix: &str) -> Vec<String> {
+    let names = prefix.strip_suffix('!').unwrap_or(prefix);
+    let names = match names.strip_prefix('\'').and_then(|n| n.strip_suffix('\'')) {
+        Some(quoted) => quoted.replace("''", "'"),
+        None => names.to_string(),
+    };
+    names.split(':').map(str::to_string).collect()
+}
+
+// ["Sheet1"] -> "Sheet1!", ["My sheet"] -> "'My sheet'!", ["Jan", "Mar"] -> "Jan:Mar!"
+pub fn sheet_prefix(names: &[&str]) -> String {
+    let joined = names.join(":");
+    if names.iter().any(|name| needs_quotes(name)) {
+        format!("'{}'!", joined.replace('\'', "''"))
+    } else {
+        format!("{}!", joined)
+    }
+}
+
+// names that would read as something else without quotes, like "My sheet", "2024" or "A1"
+fn needs_quotes(name: &str) -> bool {
+    let r1c1 = |s: &str| {
+        let s = s.to_ascii_uppercase();
+        let s = s.strip_prefix('R').map(|s| s.trim_start_matches(|c: char| c.is_ascii_digit())).unwrap_or(&s);
+        let s = s.strip_prefix('C').map(|s| s.trim_start_matches(|c: char| c.is_ascii_digit())).unwrap_or(s);
+        s.is_empty()
+    };
+    !name.starts_with(|c: char| c.is_alphabetic() || c == '_')
+        || !name.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '.')
+        || cell(name).is_some()
+        || r1c1(name)
+}
+
+
+// One part of a reference: a column or a row, fixed with '$' or relative
+#[derive(Debug, Clone, Copy)]
+struct Part {
+    fixed: bool,
+    index: u32,
+    is_col: bool,
+}
+
+impl Part {
+    fn shift(self, by: i64) -> Option<String> {
+        let index = if self.fixed { i64::from(self.index) } else { i64::from(self.index) + by };
+        let max = if self.is_col { MAX_COLS } else { MAX_ROWS };
+        if index < 0 || index >= i64::from(max) {
+            return None;
+        }
+        let dollar = if self.fixed { "$" } else { "" };
+        let index = index as u32;
+        Some(if self.is_col { format!("{}{}", dollar, column_name(index)) } else { format!("{}{}", dollar, index + 1) })
+    }
+}
+
+// "$AB" -> column 27, fixed
+fn column(word: &str) -> Option<Part> {
+    let (fixed, letters) = match word.strip_prefix('$') {
+        Some(letters) => (true, letters),
+        None => (false, word),
+    };
+    Some(Part { fixed, index: parse_column(letters)?, is_col: true })
+}
+
+// "$12" -> row 11, fixed
+fn row(word: &str) -> Option<Part> {
+    let (fixed, digits) = match word.strip_prefix('$') {
+        Some(digits) => (true, digits),
+        None => (false, word),
+    };
+    Some(Part { fixed, index: parse_row(digits)?, is_col: false })
+}
+
+// "B$3" -> (column, row)
+fn cell(word: &str) -> Option<(Part, Part)> {
+    let start = usize::from(word.starts_with('$'));
+    let split = start + word[start..].find(|c: char| !c.is_ascii_alphabetic())?;
+    let (col, row_part) = word.split_at(split);
+    Some((column(col)?, row(row_part)?))
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '.' || c == '$' || c == '\\'
+}
+
+fn word_end(s: &str, start: usize) -> usize {
+    s[start..].find(|c: char| !is_name_char(c)).map_or(s.len(), |n| start + n)
+}
+
+// end of a "string" or 'quoted name' starting at `start`, where doubled quotes stand for one
+fn quoted_end(s: &str, start: usize) -> usize {
+    let quote = &s[start..start + 1];
+    let mut i = start + 1;
+    while let Some(n) = s[i..].find(quote) {
+        i += n + 1;
+        if !s[i..].starts_with(quote) {
+            return i;
+        }
+        i += 1;
+    }
+    s.len()
+}
+
+// end of a [bracketed] part starting at `start`, which may nest as in Table1[[#This Row],[Price]]
+fn bracket_end(s: &str, start: usize) -> usize {
+    let mut depth = 0;
+    for (i, c) in s[start..].char_indices() {
+        match c {
+            '[' => depth += 1,
+            ']' => {
+                depth -= 1;
+                if depth == 0 {
+                    return start + i + 1;
+                }
+            }
+            _ => {}
+        }
+    }
+    s.len()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shift_moves_relative_parts_only() {
+        assert_eq!(shift("B2*2", 1, 0), "B3*2");
+        assert_eq!(shift("SUM($A$1:B2)+A$1", 2, 1), "SUM($A$1:C4)+B$1");
+        assert_eq!(shift("Sheet2!C:C+'My sheet'!3:4", 1, 1), "Sheet2!D:D+'My sheet'!4:5");
+        assert_eq!(shift("A1-1", -1, 0), "#REF!-1");
+    }
+
+    #[test]
+    fn shift_leaves_strings_names_and_functions() {
+        assert_eq!(shift(r#"IF(A1="B2",LOG10(A1),Rate)"#, 1, 0), r#"IF(A2="B2",LOG10(A2),Rate)"#);
+        assert_eq!(shift("Table1[[#This Row],[A1]]*#REF!", 1, 0), "Table1[[#This Row],[A1]]*#REF!");
+        assert_eq!(shift("[1]Sheet1!A1", 1, 0), "[1]Sheet1!A2");
+    }
+
+    #[test]
+    fn rename_sheet_rewrites_prefixes() {
+        assert_eq!(rename_sheet("Data!A1+data!B2", "Data", "Input").as_deref(), Some("Input!A1+Input!B2"));
+        assert_eq!(rename_sheet("SUM(Data!A:A)", "Data", "My data").as_deref(), Some("SUM('My data'!A:A)"));
+        assert_eq!(rename_sheet("'It''s'!A1", "It's", "Q1").as_deref(), Some("'Q1'!A1"));
+        assert_eq!(rename_sheet("SUM(Jan:Data!B2)", "Data", "Dec").as_deref(), Some("SUM(Jan:Dec!B2)"));
+        assert_eq!(rename_sheet("[1]Data!A1&\"Data!A1\"", "Data", "Input"), None);
+        assert_eq!(rename_sheet("Database!A1", "Data", "Input"), None);
+    }
+
+    #[test]
+    fn remove_sheet_turns_references_into_ref_errors() {
+        assert_eq!(remove_sheet("SUM(Data!C2:C4)*2", "Data").as_deref(), Some("SUM(#REF!)*2"));
+        assert_eq!(remove_sheet("'data'!$A:$A&Report!B2", "Data").as_deref(), Some("#REF!&Report!B2"));
+        assert_eq!(remove_sheet("Data!$1:$1", "Data").as_deref(), Some("#REF!"));
+        assert_eq!(remove_sheet("Data!Prices+1", "Data").as_deref(), Some("#REF!+1"));
+        assert_eq!(remove_sheet("SUM(Jan:Data!B2)", "Data").as_deref(), Some("SUM(#REF!)"));
+        assert_eq!(remove_sheet("\"Data!A1\"&Report!A1", "Data"), None);
+    }
+}
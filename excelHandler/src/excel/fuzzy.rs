@@ -0,0 +1,52 @@
+use super::address::Address;
+
+
+// A cell whose text is within the allowed edit distance of the needle
+#[derive(Debug, Clone, PartialEq)]
+pub struct FuzzyMatch {
+    pub address: Address,
+    pub text: String,
+    pub distance: usize, // Damerau-Levenshtein: insertions, deletions, substitutions and swaps
+    pub score: f64, // similarity from 0.0 to 1.0, 1.0 for an exact match
+}
+
+// (distance, score) when `text` is at most `max_distance` edits away from `needle`
+pub fn fuzzy_match(text: &str, needle: &str, max_distance: usize) -> Option<(usize, f64)> {
+    let distance = strsim::damerau_levenshtein(text, needle);
+    if distance > max_distance {
+        return None;
+    }
+    Some((distance, strsim::normalized_damerau_levenshtein(text, needle)))
+}
+
+// best candidates first, ties in scan order
+pub fn rank(matches: &mut [FuzzyMatch]) {
+    matches.sort_by_key(|m| m.distance);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_stay_within_the_distance() {
+        assert_eq!(fuzzy_match("Total", "Total", 0), Some((0, 1.0)));
+        assert_eq!(fuzzy_match("Totl", "Total", 0), None);
+        assert_eq!(fuzzy_match("Totl", "Total", 1).map(|(d, _)| d), Some(1));
+        assert_eq!(fuzzy_match("Toatl", "Total", 1).map(|(d, _)| d), Some(1)); // a swap is one edit
+        assert_eq!(fuzzy_match("total", "Total", 1).map(|(d, _)| d), Some(1)); // case counts
+        assert_eq!(fuzzy_match("Subtotal", "Total", 2), None);
+        let (_, score) = fuzzy_match("Totl", "Total", 1).unwrap();
+        assert!((score - 0.8).abs() < 1e-9);
+    }
+
+    #[test]
+    fn closest_matches_come_first() {
+        let found = |text: &str, distance| FuzzyMatch { address: Address::new(0, 0), text: text.to_string(), distance, score: 0.0 };
+        let mut matches = vec![found("b", 2), found("a", 1), found("c", 2), found("d", 0)];
+        rank(&mut matches);
+        let texts: Vec<_> = matches.iter().map(|m| m.text.as_str()).collect();
+        assert_eq!(texts, vec!["d", "a", "b", "c"]);
+    }
+}
@@ -0,0 +1,179 @@
+use calamine::Data;
+use chrono::{Duration, NaiveDate, NaiveDateTime, NaiveTime};
+
+use super::address::{Address, CellRange, MAX_COLS, MAX_ROWS};
+use super::dates::{self, DateSystem};
+use super::error::ExcelError;
+
+
+// What set_range_values and set_cell write into a cell. Formulas are given with or
+// without the leading '='; `cached` is the result shown until Excel recalculates.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CellInput {
+    Value(Data),
+    // stored as serial numbers of the workbook's date system
+    Date(NaiveDate),
+    DateTime(NaiveDateTime),
+    Duration(Duration),
+    Formula { text: String, cached: Option<Data> },
+    // a formula over `rows` x `cols` cells starting at this one, shown as {=...} in Excel
+    ArrayFormula { text: String, rows: u32, cols: u32, cached: Option<Data> },
+}
+
+impl CellInput {
+    pub fn formula(text: &str) -> Self {
+        CellInput::Formula { text: text.to_string(), cached: None }
+    }
+
+    // set_cell(sheet, range.start, CellInput::array_formula("A1:A3*B1:B3", &range))
+    pub fn array_formula(text: &str, range: &CellRange) -> Self {
+        CellInput::ArrayFormula {
+            text: text.to_string(),
+            rows: range.end.row - range.start.row + 1,
+            cols: range.end.col - range.start.col + 1,
+            cached: None,
+        }
+    }
+
+    // the result to store along with a formula
+    pub fn with_cached(self, value: Data) -> Self {
+        match self {
+            CellInput::Formula { text, .. } => CellInput::Formula { text, cached: Some(value) },
+            CellInput::ArrayFormula { text, rows, cols, .. } => CellInput::ArrayFormula { text, rows, cols, cached: Some(value) },
+            input => input,
+        }
+    }
+
+    // the formula as stored in the file, without '=' or the {=...} Excel shows around array formulas
+    pub fn formula_text(&self) -> Option<&str> {
+        let text = match self {
+            CellInput::Value(_) | CellInput::Date(_) | CellInput::DateTime(_) | CellInput::Duration(_) => return None,
+            CellInput::Formula { text, .. } => text.trim(),
+            CellInput::ArrayFormula { text, .. } => unwrap_array_braces(text.trim()),
+        };
+        Some(text.strip_prefix('=').unwrap_or(text))
+    }
+
+    // the value written to the cell: the value itself or the cached formula result.
+    // Dates have none until they are resolved.
+    pub fn value(&self) -> Option<&Data> {
+        match self {
+            CellInput::Value(value) => Some(value),
+            CellInput::Formula { cached, .. } | CellInput::ArrayFormula { cached, .. } => cached.as_ref(),
+            CellInput::Date(_) | CellInput::DateTime(_) | CellInput::Duration(_) => None,
+        }
+    }
+
+    // turn chrono values into date cells of `system`
+    pub fn resolve(self, system: DateSystem) -> Result<Self, ExcelError> {
+        let datetime = match self {
+            CellInput::Date(date) => date.and_time(NaiveTime::MIN),
+            CellInput::DateTime(datetime) => datetime,
+            CellInput::Duration(duration) => return Ok(CellInput::Value(dates::duration_value(duration))),
+            input => return Ok(input),
+        };
+        dates::datetime_value(datetime, system).map(CellInput::Value)
+            .ok_or_else(|| ExcelError::DateOutOfRange(datetime.to_string()))
+    }
+
+    // the cells an array formula written at (row, col) spans, which have to be on the sheet.
+    // None for other inputs.
+    pub fn array_range(&self, row: usize, col: usize) -> Result<Option<CellRange>, ExcelError> {
+        let (rows, cols) = match *self {
+            CellInput::ArrayFormula { rows, cols, .. } => (rows.max(1) as usize, cols.max(1) as usize),
+            _ => return Ok(None),
+        };
+        let (last_row, last_col) = (row.saturating_add(rows - 1), col.saturating_add(cols - 1));
+        if last_row >= MAX_ROWS as usize || last_col >= MAX_COLS as usize {
+            return Err(ExcelError::AddressOutOfRange { row: last_row, col: last_col });
+        }
+        let start = Address::new(row as u32, col as u32);
+        Ok(Some(CellRange::new(start, Address::new(last_row as u32, last_col as u32))))
+    }
+
+    // NaN and infinities have no representation in a workbook
+    pub fn check_finite(&self, address: Address) -> Result<(), ExcelError> {
+        match self.value() {
+            Some(&Data::Float(value)) if !value.is_finite() => Err(ExcelError::NonFiniteNumber { address, value }),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == CellInput::Value(Data::Empty)
+    }
+}
+
+impl From<Data> for CellInput {
+    fn from(value: Data) -> Self {
+        CellInput::Value(value)
+    }
+}
+
+impl From<&Data> for CellInput {
+    fn from(value: &Data) -> Self {
+        CellInput::Value(value.clone())
+    }
+}
+
+// "{=A1:A3*B1:B3}" -> "=A1:A3*B1:B3", leaving alone braces that don't wrap the whole
+// formula, as in "{1,2}+{3,4}"
+fn unwrap_array_braces(text: &str) -> &str {
+    let inner = match text.strip_prefix("{=").and_then(|t| t.strip_suffix('}')) {
+        Some(_) => &text[1..text.len() - 1],
+        None => return text,
+    };
+    let mut depth = 0;
+    let mut quote = None; // inside "text" or a 'sheet name'
+    for ch in inner.chars() {
+        match (quote, ch) {
+            (Some(q), _) if ch == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"') | (None, '\'') => quote = Some(ch),
+            (None, '{') => depth += 1,
+            (None, '}') if depth == 0 => return text,
+            (None, '}') => depth -= 1,
+            _ => {}
+        }
+    }
+    inner
+}
+
+impl From<NaiveDate> for CellInput {
+    fn from(date: NaiveDate) -> Self {
+        CellInput::Date(date)
+    }
+}
+
+impl From<NaiveDateTime> for CellInput {
+    fn from(datetime: NaiveDateTime) -> Self {
+        CellInput::DateTime(datetime)
+    }
+}
+
+impl From<Duration> for CellInput {
+    fn from(duration: Duration) -> Self {
+        CellInput::Duration(duration)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn array(text: &str) -> CellInput {
+        CellInput::array_formula(text, &"A1:A2".parse().unwrap())
+    }
+
+    #[test]
+    fn formula_text_keeps_array_constants() {
+        assert_eq!(CellInput::formula("{1,2}").formula_text(), Some("{1,2}"));
+        assert_eq!(CellInput::formula("=SUM({1,2})").formula_text(), Some("SUM({1,2})"));
+        assert_eq!(array("{=A1:A2*{1;2}}").formula_text(), Some("A1:A2*{1;2}"));
+        assert_eq!(array("=A1:A2").formula_text(), Some("A1:A2"));
+        assert_eq!(array("{=A1:A2}+{3,4}").formula_text(), Some("{=A1:A2}+{3,4}"));
+        assert_eq!(array(r#"{=IF(A1:A2="}",1,2)}"#).formula_text(), Some(r#"IF(A1:A2="}",1,2)"#));
+        assert_eq!(array("{1,2}").formula_text(), Some("{1,2}"));
+    }
+}
@@ -0,0 +1,313 @@
+// every unsafe block states why its FFI call is sound
+#![deny(clippy::undocumented_unsafe_blocks)]
+
+use std::ffi::CString;
+use std::marker::PhantomData;
+use std::ptr;
+
+use libxlsxwriter_sys as sys;
+use xlsxwriter::XlsxError;
+
+use super::error::ExcelError;
+
+// LXW_DEF_ROW_HEIGHT and LXW_DEF_COL_WIDTH, which are macros the bindings leave out
+const DEFAULT_ROW_HEIGHT: f64 = 15.0;
+const DEFAULT_COL_WIDTH: f64 = 8.43;
+
+// A libxlsxwriter workbook driven through its C API, as xlsxwriter 0.2 can't open one
+// with options. With constant_memory each row goes to a temporary file as soon as a
+// later row is written, so rows have to come in order.
+// Sheets and formats are referred to by the index they were added under.
+pub struct Workbook {
+    raw: *mut sys::lxw_workbook, // null once closed
+    sheets: Vec<*mut sys::lxw_worksheet>,
+    formats: Vec<*mut sys::lxw_format>,
+}
+
+// SAFETY: the workbook owns everything behind its pointers, libxlsxwriter keeps no
+// thread-local state, and ExcelHandle only reaches it through a Mutex, one thread at a time
+unsafe impl Send for Workbook {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FormatId(usize);
+
+impl Workbook {
+    // the file is only written on close
+    pub fn new(path: &str, constant_memory: bool) -> Result<Self, ExcelError> {
+        let path = c_string(path)?;
+        let mut options = sys::lxw_workbook_options {
+            constant_memory: constant_memory as u8,
+            tmpdir: ptr::null_mut(),
+            use_zip64: 0,
+        };
+        // SAFETY: both arguments outlive the call, libxlsxwriter copies what it keeps
+        let raw = unsafe { sys::workbook_new_opt(path.as_ptr(), &mut options) };
+        if raw.is_null() {
+            return Err(error(sys::lxw_error_LXW_ERROR_MEMORY_MALLOC_FAILED));
+        }
+        Ok(Self { raw, sheets: Vec::new(), formats: Vec::new() })
+    }
+
+    // a sheet after the existing ones; the name has to be valid and unused
+    pub fn add_worksheet(&mut self, name: &str) -> Result<usize, ExcelError> {
+        let raw = self.raw()?;
+        let name = c_string(name)?;
+        // SAFETY: raw() only returns an open workbook; the name is copied
+        let sheet = unsafe { sys::workbook_add_worksheet(raw, name.as_ptr()) };
+        if sheet.is_null() {
+            // constant-memory sheets fail when their temporary file can't be created
+            return Err(error(sys::lxw_error_LXW_ERROR_CREATING_TMPFILE));
+        }
+        self.sheets.push(sheet);
+        Ok(self.sheets.len() - 1)
+    }
+
+    pub fn add_format(&mut self) -> Result<Format<'_>, ExcelError> {
+        // SAFETY: raw() only returns an open workbook
+        let raw = unsafe { sys::workbook_add_format(self.raw()?) };
+        if raw.is_null() {
+            return Err(error(sys::lxw_error_LXW_ERROR_MEMORY_MALLOC_FAILED));
+        }
+        self.formats.push(raw);
+        Ok(Format { raw, id: FormatId(self.formats.len() - 1), workbook: PhantomData })
+    }
+
+    pub fn write_number(&mut self, sheet: usize, row: u32, col: u16, number: f64, format: Option<FormatId>)
+        -> Result<(), ExcelError>
+    {
+        // SAFETY: see sheet() and format()
+        check(unsafe { sys::worksheet_write_number(self.sheet(sheet)?, row, col, number, self.format(format)) })
+    }
+
+    pub fn write_string(&mut self, sheet: usize, row: u32, col: u16, text: &str, format: Option<FormatId>)
+        -> Result<(), ExcelError>
+    {
+        let text = c_string(text)?;
+        // SAFETY: see sheet() and format(); the text is copied
+        check(unsafe { sys::worksheet_write_string(self.sheet(sheet)?, row, col, text.as_ptr(), self.format(format)) })
+    }
+
+    pub fn write_boolean(&mut self, sheet: usize, row: u32, col: u16, value: bool, format: Option<FormatId>)
+        -> Result<(), ExcelError>
+    {
+        // SAFETY: see sheet() and format()
+        check(unsafe { sys::worksheet_write_boolean(self.sheet(sheet)?, row, col, value as _, self.format(format)) })
+    }
+
+    // a formula with "=", and the result to show until Excel recalculates it
+    pub fn write_formula(&mut self,
+            sheet: usize,
+            row: u32,
+            col: u16,
+            formula: &str,
+            result: Option<f64>,
+            format: Option<FormatId>
+        )
+        -> Result<(), ExcelError>
+    {
+        let formula = c_string(formula)?;
+        let (sheet, format) = (self.sheet(sheet)?, self.format(format));
+        // SAFETY: see sheet() and format(); the formula is copied
+        check(unsafe {
+            match result {
+                Some(result) => sys::worksheet_write_formula_num(sheet, row, col, formula.as_ptr(), format, result),
+                None => sys::worksheet_write_formula(sheet, row, col, formula.as_ptr(), format),
+            }
+        })
+    }
+
+    // an array formula over first..=last, given as (row, col)
+    pub fn write_array_formula(&mut self,
+            sheet: usize,
+            first: (u32, u16),
+            last: (u32, u16),
+            formula: &str,
+            format: Option<FormatId>
+        )
+        -> Result<(), ExcelError>
+    {
+        let formula = c_string(formula)?;
+        let (sheet, format) = (self.sheet(sheet)?, self.format(format));
+        // libxlsxwriter only takes the last cell into the sheet's dimension, and with
+        // constant_memory a row is written out from its dimension's first column on.
+        // The placeholder makes the first cell count and is overwritten right after.
+        // SAFETY: see sheet() and format(); the formula is copied
+        check(unsafe { sys::worksheet_write_number(sheet, first.0, first.1, 0.0, format) })?;
+        // SAFETY: as above
+        check(unsafe {
+            sys::worksheet_write_array_formula(sheet, first.0, first.1, last.0, last.1, formula.as_ptr(), format)
+        })
+    }
+
+    // a cell with a format and no value
+    pub fn write_blank(&mut self, sheet: usize, row: u32, col: u16, format: Option<FormatId>) -> Result<(), ExcelError> {
+        // SAFETY: see sheet() and format()
+        check(unsafe { sys::worksheet_write_blank(self.sheet(sheet)?, row, col, self.format(format)) })
+    }
+
+    // the format of the cells of a row that have none of their own, which with
+    // constant_memory writes out the rows before it
+    pub fn set_row(&mut self, sheet: usize, row: u32, format: Option<FormatId>) -> Result<(), ExcelError> {
+        let (sheet, format) = (self.sheet(sheet)?, self.format(format));
+        // SAFETY: see sheet() and format()
+        check(unsafe { sys::worksheet_set_row(sheet, row, DEFAULT_ROW_HEIGHT, format) })
+    }
+
+    // the format of the cells of columns first..=last that have none of their own
+    // or of their row
+    pub fn set_column(&mut self, sheet: usize, first: u16, last: u16, format: Option<FormatId>)
+        -> Result<(), ExcelError>
+    {
+        let (sheet, format) = (self.sheet(sheet)?, self.format(format));
+        // SAFETY: see sheet() and format()
+        check(unsafe { sys::worksheet_set_column(sheet, first, last, DEFAULT_COL_WIDTH, format) })
+    }
+
+    // write the file and free the workbook; later calls do nothing
+    pub fn close(&mut self) -> Result<(), ExcelError> {
+        if self.raw.is_null() {
+            return Ok(());
+        }
+        // SAFETY: the workbook is open, and the pointers into it are dropped with it right after
+        let result = unsafe { sys::workbook_close(self.raw) };
+        self.raw = ptr::null_mut();
+        self.sheets.clear();
+        self.formats.clear();
+        check(result)
+    }
+
+    fn raw(&self) -> Result<*mut sys::lxw_workbook, ExcelError> {
+        if self.raw.is_null() {
+            return Err(ExcelError::AlreadySaved);
+        }
+        Ok(self.raw)
+    }
+
+    // a sheet of the open workbook, which libxlsxwriter frees only on close
+    fn sheet(&self, sheet: usize) -> Result<*mut sys::lxw_worksheet, ExcelError> {
+        self.raw()?;
+        self.sheets.get(sheet).copied().ok_or_else(|| error(sys::lxw_error_LXW_ERROR_WORKSHEET_INDEX_OUT_OF_RANGE))
+    }
+
+    // a format of the workbook or null, which libxlsxwriter reads as the default format.
+    // Formats, like sheets, are freed only on close.
+    fn format(&self, format: Option<FormatId>) -> *mut sys::lxw_format {
+        format.and_then(|FormatId(i)| self.formats.get(i).copied()).unwrap_or(ptr::null_mut())
+    }
+}
+
+// as xlsxwriter does, a workbook that was never closed is written when dropped
+impl Drop for Workbook {
+    fn drop(&mut self) {
+        let _ = self.close();
+    }
+}
+
+
+// A cell format being set up, see CellStyle::apply.
+// It borrows the workbook, which therefore stays open and keeps `raw` alive.
+pub struct Format<'a> {
+    raw: *mut sys::lxw_format,
+    id: FormatId,
+    workbook: PhantomData<&'a mut Workbook>,
+}
+
+impl Format<'_> {
+    pub fn id(&self) -> FormatId {
+        self.id
+    }
+
+    pub fn set_font_name(&mut self, name: &str) {
+        if let Ok(name) = CString::new(name) {
+            // SAFETY: see Format; strings are copied
+            unsafe { sys::format_set_font_name(self.raw, name.as_ptr()) }
+        }
+    }
+
+    pub fn set_font_size(&mut self, size: f64) {
+        // SAFETY: see Format
+        unsafe { sys::format_set_font_size(self.raw, size) }
+    }
+
+    pub fn set_bold(&mut self) {
+        // SAFETY: see Format
+        unsafe { sys::format_set_bold(self.raw) }
+    }
+
+    pub fn set_italic(&mut self) {
+        // SAFETY: see Format
+        unsafe { sys::format_set_italic(self.raw) }
+    }
+
+    pub fn set_underline(&mut self) {
+        // SAFETY: see Format
+        unsafe { sys::format_set_underline(self.raw, sys::lxw_format_underlines_LXW_UNDERLINE_SINGLE as u8) }
+    }
+
+    pub fn set_font_color(&mut self, rgb: u32) {
+        // SAFETY: see Format
+        unsafe { sys::format_set_font_color(self.raw, color(rgb)) }
+    }
+
+    // a solid background
+    pub fn set_fill(&mut self, rgb: u32) {
+        // SAFETY: see Format
+        unsafe {
+            sys::format_set_pattern(self.raw, sys::lxw_format_patterns_LXW_PATTERN_SOLID as u8);
+            sys::format_set_bg_color(self.raw, color(rgb));
+        }
+    }
+
+    // on all four sides
+    pub fn set_border(&mut self, border: sys::lxw_format_borders) {
+        // SAFETY: see Format
+        unsafe { sys::format_set_border(self.raw, border as u8) }
+    }
+
+    pub fn set_border_color(&mut self, rgb: u32) {
+        // SAFETY: see Format
+        unsafe { sys::format_set_border_color(self.raw, color(rgb)) }
+    }
+
+    // horizontal and vertical alignments are set one at a time
+    pub fn set_align(&mut self, align: sys::lxw_format_alignments) {
+        // SAFETY: see Format
+        unsafe { sys::format_set_align(self.raw, align as u8) }
+    }
+
+    pub fn set_text_wrap(&mut self) {
+        // SAFETY: see Format
+        unsafe { sys::format_set_text_wrap(self.raw) }
+    }
+
+    pub fn set_num_format(&mut self, num_format: &str) {
+        if let Ok(num_format) = CString::new(num_format) {
+            // SAFETY: see Format; strings are copied
+            unsafe { sys::format_set_num_format(self.raw, num_format.as_ptr()) }
+        }
+    }
+}
+
+// libxlsxwriter reads a plain 0 as unset
+fn color(rgb: u32) -> sys::lxw_color_t {
+    match rgb & 0xFF_FFFF {
+        0 => sys::lxw_defined_colors_LXW_COLOR_BLACK as sys::lxw_color_t,
+        rgb => rgb as sys::lxw_color_t,
+    }
+}
+
+// C strings can't hold NUL
+fn c_string(s: &str) -> Result<CString, ExcelError> {
+    CString::new(s).map_err(|_| error(sys::lxw_error_LXW_ERROR_PARAMETER_VALIDATION))
+}
+
+fn check(result: sys::lxw_error) -> Result<(), ExcelError> {
+    match result {
+        sys::lxw_error_LXW_NO_ERROR => Ok(()),
+        e => Err(error(e)),
+    }
+}
+
+fn error(e: sys::lxw_error) -> ExcelError {
+    ExcelError::Write(XlsxError::new(e))
+}
@@ -1,23 +1,88 @@
-use std::cell::RefCell;
 use std::fmt;
 use std::fs::File;
-use calamine::{Reader, open_workbook, Error};
-use xlsxwriter::Workbook;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use calamine::{Reader, Sheets};
+use rayon::prelude::*;
+use serde::de::DeserializeOwned;
 
+mod address;
+mod cache;
+mod dates;
+mod de;
+mod error;
+mod formula;
+mod fuzzy;
+mod input;
+mod lxw;
 mod reader;
-pub use reader::CellValue;
+mod writer;
+mod package;
+mod creater;
+mod normalize;
+mod predicate;
+mod scan;
+mod sheetname;
+mod stream;
+mod style;
+pub use address::{Address, CellRange};
+pub use dates::{DateSystem, DateValue};
+pub use error::ExcelError;
+pub use fuzzy::FuzzyMatch;
+pub use input::CellInput;
+pub use normalize::Normalization;
+pub use predicate::{CellPredicate, RowPredicate, Predicate, Pattern};
+pub use reader::{CellValue, Formulas, Worksheet, RowValues};
+pub use scan::{Scan, Indices, Order};
+pub use sheetname::SheetNameError;
+pub use stream::{RowStream, Rows};
+pub use style::{CellStyle, Border, Align, VAlign};
+pub use calamine::DataType; // is_string(), is_empty(), ... on CellValue
 
 
-pub struct ExcelHandle {
-    wb: RefCell<Wb>, // internal mutability
+// The type parameter is the access mode: ReadOnly, ReadWrite or New.
+// Write methods only exist for the writable ones.
+pub struct ExcelHandle<M> {
+    wb: Mutex<Wb>, // internal mutability, shareable across threads
+    cache: cache::SheetCache,
     path: String,
-    mode: Mode,
+    clamp: bool,
+    stream: bool,
+    save_tried: AtomicBool, // a created workbook was saved, or failed to, so drop leaves it alone
+    mode: PhantomData<M>,
 }
 
+pub struct ReadOnly;
+pub struct ReadWrite;
+pub struct New;
+
+pub trait Access {
+    const MODE: Mode;
+}
+
+impl Access for ReadOnly {
+    const MODE: Mode = Mode::Read;
+}
+
+impl Access for ReadWrite {
+    const MODE: Mode = Mode::Write;
+}
+
+impl Access for New {
+    const MODE: Mode = Mode::Create;
+}
+
+// modes that allow writing
+pub trait Writable: Access {}
+
+impl Writable for ReadWrite {}
+impl Writable for New {}
+
 enum Wb {
-    Reader(reader::XlsxReader),
-    Writer(reader::XlsxReader),
-    Creater(Workbook),
+    Reader(reader::ExcelReader),
+    Writer(reader::ExcelReader), // always Sheets::Xlsx
+    Creater(creater::Book),
 }
 
 #[derive(PartialEq, Debug)]
@@ -38,127 +103,652 @@ impl fmt::Display for Mode {
     }
 }
 
-impl ExcelHandle {
-    pub fn new(file_path: String, mode: Mode) -> Result<Self, Error> {
-        match mode {
-            Mode::Read => Ok(Self {
-                wb: RefCell::new(Wb::Reader(open_workbook(&file_path)?)),
-                path: file_path,
-                mode: mode,
-            }),
-            Mode::Write => Ok(Self {
-                wb: RefCell::new(Wb::Writer(open_workbook(&file_path)?)),
-                path: file_path,
-                mode: mode,
-            }),
-            _ => {
-                    if let Ok(_) = File::open(&file_path) {
-                        return Err(Error::Msg("File with same name already exists"))
-                    }
-                    Ok(Self {
-                    wb: RefCell::new(Wb::Creater(Workbook::new(&file_path))),
-                    path: file_path,
-                    mode: mode,
-                })
-            }
+impl ExcelHandle<ReadOnly> {
+    pub fn new(file_path: String) -> Result<Self, ExcelError> {
+        Ok(Self {
+            wb: Mutex::new(Wb::Reader(reader::open_reader(&file_path)?)),
+            cache: cache::SheetCache::default(),
+            path: file_path,
+            clamp: true,
+            stream: false,
+            save_tried: AtomicBool::new(false),
+            mode: PhantomData,
+        })
+    }
+}
+
+impl ExcelHandle<ReadWrite> {
+    // only .xlsx and .xlsm files can be edited
+    pub fn new(file_path: String) -> Result<Self, ExcelError> {
+        let wb = reader::open_reader(&file_path)?;
+        if !matches!(wb, Sheets::Xlsx(_)) {
+            return Err(ExcelError::ReadOnly);
         }
+        Ok(Self {
+            wb: Mutex::new(Wb::Writer(wb)),
+            cache: cache::SheetCache::default(),
+            path: file_path,
+            clamp: true,
+            stream: false,
+            save_tried: AtomicBool::new(false),
+            mode: PhantomData,
+        })
     }
+}
 
-    // return all sheetnames
-    pub fn get_sheetnames(&self) -> Vec<String> {
-        match *self.wb.borrow() {
-            Wb::Reader(ref wb) => wb.sheet_names().to_owned(),
-            _ => vec![]
+impl ExcelHandle<New> {
+    pub fn new(file_path: String) -> Result<Self, ExcelError> {
+        Self::create(file_path, false)
+    }
+
+    // a workbook whose rows go straight to temporary files instead of memory, for very
+    // large reports, using libxlsxwriter's constant_memory mode. Each sheet is written in
+    // row order: use append_row, or set_range_values below the last written row.
+    // Sheets can only be added after the last one, and not renamed, deleted or reordered.
+    pub fn new_constant_memory(file_path: String) -> Result<Self, ExcelError> {
+        Self::create(file_path, true)
+    }
+
+    fn create(file_path: String, constant_memory: bool) -> Result<Self, ExcelError> {
+        if File::open(&file_path).is_ok() {
+            return Err(ExcelError::FileExists(file_path))
         }
+        let book = if constant_memory { creater::Book::constant_memory(&file_path)? } else { creater::Book::default() };
+        Ok(Self {
+            wb: Mutex::new(Wb::Creater(book)),
+            cache: cache::SheetCache::default(),
+            path: file_path,
+            clamp: true,
+            stream: false,
+            save_tried: AtomicBool::new(false),
+            mode: PhantomData,
+        })
+    }
+}
+
+impl<M> ExcelHandle<M> {
+    // a poisoned lock is still usable, Wb is only ever replaced as a whole
+    fn wb(&self) -> MutexGuard<'_, Wb> {
+        self.wb.lock().unwrap_or_else(PoisonError::into_inner)
     }
+}
+
+impl<M: Access> ExcelHandle<M> {
+    pub fn mode(&self) -> Mode {
+        M::MODE
+    }
+
+    // whether open-ended rows and cols stop at the end of a sheet's used range (the default)
+    pub fn clamp_to_used_range(mut self, clamp: bool) -> Self {
+        self.clamp = clamp;
+        self
+    }
+
+    // let find_cell read sheets that aren't cached row by row and stop at the first match,
+    // instead of parsing them whole. Only for scans with ascending rows in row-major order.
+    pub fn streaming(mut self, stream: bool) -> Self {
+        self.stream = stream;
+        self
+    }
+
+    // the date system of the workbook, to read numbers as dates with DateValue:
+    //   value.to_date(ex.date_system())
+    pub fn date_system(&self) -> DateSystem {
+        date_system(&self.wb())
+    }
+
+    // read a sheet row by row without loading it into memory:
+    //   for row in ex.stream_rows("Sheet1")?.rows()? { let (row, values) = row?; }
+    // A created workbook streams no rows until it is saved.
+    pub fn stream_rows(&self, sheetname: &str) -> Result<RowStream, ExcelError> {
+        if let Wb::Creater(ref book) = *self.wb() {
+            return book.position(sheetname).map(|_| stream::empty_stream(sheetname));
+        }
+        stream::stream_reader(&self.path, sheetname)
+    }
+
+    // cap the estimated memory of parsed sheets kept between calls, None for no limit (the default)
+    pub fn cache_limit(self, bytes: Option<usize>) -> Self {
+        self.cache.set_limit(bytes);
+        self
+    }
+
+    // drop a parsed sheet, it is read from the file again on next use
+    pub fn evict(&self, sheetname: &str) {
+        self.cache.evict(sheetname);
+    }
+
+    pub fn clear_cache(&self) {
+        self.cache.clear();
+    }
+
+    // estimated bytes held by parsed sheets
+    pub fn cache_size(&self) -> usize {
+        self.cache.size()
+    }
+}
+
+
+// Read methods, shared by every mode
+pub trait ExcelRead {
+    // return all sheetnames
+    fn get_sheetnames(&self) -> Result<Vec<String>, ExcelError>;
+
+    // parse a sheet, for Worksheet::iter_row_values
+    fn worksheet(&self, sheetname: &str) -> Result<Worksheet, ExcelError>;
+
+    // parse the formulas of a sheet
+    fn formulas(&self, sheetname: &str) -> Result<Formulas, ExcelError>;
 
     // return a list of sheet names
-    pub fn find_sheets<I, J>(&self,
-            rows: &impl Fn() -> I,
-            cols: &impl Fn() -> J,
-            func: &dyn Fn(&CellValue) -> bool
-        )
-        -> Vec<String>
+    fn find_sheets(&self, scan: &Scan, func: &dyn CellPredicate) -> Result<Vec<String>, ExcelError>;
+
+    // return cell absolute address
+    fn find_cell<F>(&self, sheetname: &str, scan: &Scan, func: F) -> Result<Option<(u32, u32)>, ExcelError>
+        where
+            F: CellPredicate;
+
+    // return every matching cell address in scan order
+    fn find_all_cells<F>(&self, sheetname: &str, scan: &Scan, func: F) -> Result<Vec<(u32, u32)>, ExcelError>
+        where
+            F: CellPredicate;
+
+    // return (sheet, row, col) of every matching cell, sheet by sheet
+    fn find_in_workbook(&self, scan: &Scan, func: &dyn CellPredicate)
+        -> Result<Vec<(String, u32, u32)>, ExcelError>;
+
+    // retrun a vector of cell value vectors, one entry per row and Empty for missing cells.
+    // iterate_row_values_compact leaves empty cells out.
+    fn iterate_row_values<F>(&self, sheetname: &str, scan: &Scan, func: F) -> Result<Vec<Vec<CellValue>>, ExcelError>
+        where
+            F: RowPredicate;
+
+    // iterate_row_values without the empty cells, so that entries no longer line up by row
+    fn iterate_row_values_compact<F>(&self, sheetname: &str, scan: &Scan, func: F)
+        -> Result<Vec<Vec<CellValue>>, ExcelError>
+        where
+            F: RowPredicate,
+    {
+        Ok(self.worksheet(sheetname)?.iter_row_values(scan, func).compact().collect())
+    }
+
+    // the formula of a cell without the leading '=', None for a constant or empty cell
+    fn get_formula(&self, sheetname: &str, at: Address) -> Result<Option<String>, ExcelError> {
+        Ok(self.formulas(sheetname)?.get(at).map(str::to_string))
+    }
+
+    // cells whose formula text matches, e.g. Predicate::contains("VLOOKUP")
+    fn find_formulas<F>(&self, sheetname: &str, scan: &Scan, func: F) -> Result<Vec<(u32, u32)>, ExcelError>
+        where
+            F: CellPredicate,
+    {
+        Ok(self.formulas(sheetname)?.find_all_cells(scan, func))
+    }
+
+    // candidate cells for a possibly misspelled label, ranked by edit distance
+    fn find_cell_fuzzy(&self, sheetname: &str, scan: &Scan, needle: &str, max_distance: usize)
+        -> Result<Vec<FuzzyMatch>, ExcelError>
+    {
+        Ok(self.worksheet(sheetname)?.find_fuzzy(scan, needle, max_distance))
+    }
+
+    // rows below `header_row` (0-based) as structs, e.g. #[derive(Deserialize)] struct Item { name: String, price: Option<f64> }.
+    // Columns are matched by header text, #[serde(rename = "Unit price")] for other names.
+    // Blank cells are None, numbers and text convert either way, errors name the cell.
+    fn deserialize<T>(&self, sheetname: &str, header_row: u32) -> Result<Vec<T>, ExcelError>
+        where
+            T: DeserializeOwned,
+    {
+        self.worksheet(sheetname)?.deserialize(header_row)
+    }
+
+    // find_cell over an A1 range with a sheet name, e.g. "Sheet1!A1:C10"
+    fn find_cell_in<F>(&self, range: &CellRange, func: F) -> Result<Option<Address>, ExcelError>
         where
-            I: Iterator<Item=u32>,
-            J: Iterator<Item=u32>,
+            F: CellPredicate,
     {
+        let found = self.find_cell(range.sheetname()?, &Scan::from(range), func)?;
+        Ok(found.map(Address::from))
+    }
+
+    // find_all_cells over an A1 range with a sheet name
+    fn find_all_cells_in<F>(&self, range: &CellRange, func: F) -> Result<Vec<Address>, ExcelError>
+        where
+            F: CellPredicate,
+    {
+        let found = self.find_all_cells(range.sheetname()?, &Scan::from(range), func)?;
+        Ok(found.into_iter().map(Address::from).collect())
+    }
+
+    // iterate_row_values over an A1 range with a sheet name
+    fn iterate_row_values_in<F>(&self, range: &CellRange, func: F) -> Result<Vec<Vec<CellValue>>, ExcelError>
+        where
+            F: RowPredicate,
+    {
+        self.iterate_row_values(range.sheetname()?, &Scan::from(range), func)
+    }
+}
+
+impl<M> ExcelRead for ExcelHandle<M> {
+    // return all sheetnames
+    fn get_sheetnames(&self) -> Result<Vec<String>, ExcelError> {
+        match *self.wb() {
+            Wb::Reader(ref wb) | Wb::Writer(ref wb) => Ok(wb.sheet_names().to_owned()),
+            Wb::Creater(ref book) => Ok(book.sheet_names()),
+        }
+    }
+
+    // parse a sheet, for Worksheet::iter_row_values
+    fn worksheet(&self, sheetname: &str) -> Result<Worksheet, ExcelError> {
+        match *self.wb() {
+            Wb::Reader(ref mut range) | Wb::Writer(ref mut range) => {
+                let worksheet = reader::worksheet_reader(range, &self.cache, sheetname)?;
+                Ok(worksheet.clamp_to_used_range(self.clamp))
+            }
+            Wb::Creater(ref book) => book.position(sheetname).map(|_| Worksheet::empty()), // nothing to read before save
+        }
+    }
+
+    // parse the formulas of a sheet
+    fn formulas(&self, sheetname: &str) -> Result<Formulas, ExcelError> {
+        match *self.wb() {
+            Wb::Reader(ref mut r) | Wb::Writer(ref mut r) => reader::formulas_reader(r, sheetname),
+            Wb::Creater(ref book) => book.position(sheetname).map(|_| Formulas::empty()), // nothing to read before save
+        }
+    }
+
+    // return a list of sheet names
+    fn find_sheets(&self, scan: &Scan, func: &dyn CellPredicate) -> Result<Vec<String>, ExcelError> {
         let mut sheets = Vec::new();
-        for sheet in self.get_sheetnames() {
-            if let Some(_) = self.find_cell(sheet.as_str(), rows, cols, &func) {
+        for sheet in self.get_sheetnames()? {
+            if self.find_cell(sheet.as_str(), scan, |value: &CellValue| func.matches(value))?.is_some() {
                 sheets.push(sheet);
             }
         }
-        sheets
+        Ok(sheets)
     }
 
     // return cell absolute address
-    pub fn find_cell<I, J, F>(&self,
-            sheetname: &str,
-            rows: &impl Fn() -> I,
-            cols: &impl Fn() -> J,
-            func: F
-        )
-        -> Option<(u32, u32)> 
+    fn find_cell<F>(&self, sheetname: &str, scan: &Scan, func: F) -> Result<Option<(u32, u32)>, ExcelError>
+        where
+            F: CellPredicate,
+    {
+        let creating = matches!(*self.wb(), Wb::Creater(_));
+        if self.stream && !creating && stream::is_streamable(scan) && self.cache.get(sheetname).is_none() {
+            return stream::stream_reader(&self.path, sheetname)?.find_cell(scan, func);
+        }
+        Ok(self.worksheet(sheetname)?.find_cell(scan, func))
+    }
+
+    // return every matching cell address in scan order
+    fn find_all_cells<F>(&self, sheetname: &str, scan: &Scan, func: F) -> Result<Vec<(u32, u32)>, ExcelError>
         where
-            I: Iterator<Item=u32>,
-            J: Iterator<Item=u32>,
-            F: Fn(&CellValue) -> bool,
+            F: CellPredicate,
     {
-        match *self.wb.borrow_mut() {
-            Wb::Reader(ref mut range) => {
-                reader::find_cell_reader(range, sheetname, rows, cols, func)
+        Ok(self.worksheet(sheetname)?.find_all_cells(scan, func))
+    }
+
+    // return (sheet, row, col) of every matching cell, sheet by sheet
+    fn find_in_workbook(&self, scan: &Scan, func: &dyn CellPredicate)
+        -> Result<Vec<(String, u32, u32)>, ExcelError>
+    {
+        let mut cells = Vec::new();
+        for sheet in self.get_sheetnames()? {
+            for (row, col) in self.find_all_cells(sheet.as_str(), scan, |value: &CellValue| func.matches(value))? {
+                cells.push((sheet.clone(), row, col));
             }
-            _ => None
         }
+        Ok(cells)
     }
 
 
     // retrun a vector of cell value vectors
-    pub fn iterate_row_values<I, J, F>(&self,
+    fn iterate_row_values<F>(&self, sheetname: &str, scan: &Scan, func: F) -> Result<Vec<Vec<CellValue>>, ExcelError>
+        where
+            F: RowPredicate,
+    {
+        let worksheet = self.worksheet(sheetname)?;
+        let values = worksheet.iter_row_values(scan, func).collect();
+        Ok(values)
+    }
+}
+
+
+// Parallel search, sheets are parsed and scanned on rayon's thread pool
+impl<M: Sync> ExcelHandle<M> {
+    // find_sheets on several threads for workbooks with many sheets, results in sheet order.
+    // The sheets are split into one run per thread, each parsed with a reader of its own.
+    pub fn find_sheets_par(&self, scan: &Scan, func: &(dyn CellPredicate + Sync)) -> Result<Vec<String>, ExcelError> {
+        let creating = matches!(*self.wb(), Wb::Creater(_));
+        if creating {
+            return self.find_sheets(scan, func); // nothing to parse before save
+        }
+        let sheets = self.get_sheetnames()?;
+        let run = sheets.len().div_ceil(rayon::current_num_threads()).max(1);
+        let found = sheets
+            .par_chunks(run)
+            .map(|sheets| {
+                let mut r = None;
+                let mut found = Vec::new();
+                for sheet in sheets {
+                    let worksheet = reader::worksheet_reader_shared(&mut r, &self.path, &self.cache, sheet)?;
+                    if worksheet.clamp_to_used_range(self.clamp).find_cell(scan, |value: &CellValue| func.matches(value)).is_some() {
+                        found.push(sheet.clone());
+                    }
+                }
+                Ok(found)
+            })
+            .collect::<Result<Vec<_>, ExcelError>>()?;
+        Ok(found.concat())
+    }
+}
+
+
+// Methods only when writable is True
+impl<M: Writable> ExcelHandle<M> {
+    // write a data matrix with its top-left cell at (row, col).
+    // Cells take a CellValue, or a CellInput to write formulas and chrono dates.
+    // An array formula is only replaced as a whole, by writing its first cell.
+    pub fn set_range_values<T>(&self,
             sheetname: &str,
-            rows: &impl Fn() -> I,
-            cols: &impl Fn() -> J,
-            func: F
+            row: u32,
+            col: u32,
+            data: &[Vec<T>]
         )
-        -> Vec<Vec<CellValue>>
+        -> Result<(), ExcelError>
         where
-            I: Iterator<Item=u32>,
-            J: Iterator<Item=u32>,
-            F: Fn(&Vec<CellValue>) -> bool,
+            T: Clone + Into<CellInput>,
     {
-        match *self.wb.borrow_mut() {
-            Wb::Reader(ref mut range) => {
-                reader::iterate_row_values_reader(range, sheetname, rows, cols, func)
+        let mut wb = self.wb();
+        let system = date_system(&wb);
+        let data = data.iter().map(|values| inputs(values, system)).collect::<Result<Vec<_>, _>>()?;
+        let data = data.as_slice();
+        match *wb {
+            Wb::Writer(_) => {
+                writer::set_range_values_writer(&self.path, sheetname, row, col, data)?;
+                self.evict(sheetname);
+                *wb = Wb::Writer(reader::open_reader(&self.path)?); // reload to see the written values
+                Ok(())
             }
-            _ => vec![]
+            Wb::Creater(ref mut book) => creater::set_range_values_creater(book, sheetname, row, col, data, None),
+            Wb::Reader(_) => unreachable!(),
         }
     }
 
-    // Methods only when writable is True
+    // set_range_values with the top-left cell given as an Address, e.g. "B3".parse()?
+    pub fn set_range_values_at<T>(&self,
+            sheetname: &str,
+            at: Address,
+            data: &[Vec<T>]
+        )
+        -> Result<(), ExcelError>
+        where
+            T: Clone + Into<CellInput>,
+    {
+        self.set_range_values(sheetname, at.row, at.col, data)
+    }
+
+    // write a single value or formula, e.g. set_cell("Sheet1", "C1".parse()?, CellInput::formula("A1+B1"))
+    pub fn set_cell<T: Into<CellInput>>(&self, sheetname: &str, at: Address, input: T) -> Result<(), ExcelError> {
+        self.set_range_values(sheetname, at.row, at.col, &[vec![input.into()]])
+    }
+
+    // write `values` from column A into the row below the last used row of the sheet
+    pub fn append_row<T>(&self, sheetname: &str, values: &[T]) -> Result<(), ExcelError>
+        where
+            T: Clone + Into<CellInput>,
+    {
+        if let Wb::Creater(ref mut book) = *self.wb() {
+            return creater::append_row_creater(book, sheetname, &inputs(values, DateSystem::V1900)?, None);
+        }
+        let row = self.worksheet(sheetname)?.last_cell().map_or(0, |last| last.row + 1);
+        self.set_range_values(sheetname, row, 0, &[values.to_vec()])
+    }
+
+    // add an empty sheet at `index` in the tab order, or after the last sheet
+    pub fn add_sheet(&self, sheetname: &str, index: Option<usize>) -> Result<(), ExcelError> {
+        let sheets = self.get_sheetnames()?;
+        let index = index.unwrap_or(sheets.len());
+        if index > sheets.len() {
+            return Err(ExcelError::SheetIndexOutOfRange(index));
+        }
+        sheetname::validate_sheet_name(sheetname, &sheets)?;
+        let mut wb = self.wb();
+        match *wb {
+            Wb::Writer(_) => {
+                writer::add_sheet_writer(&self.path, sheetname, index)?;
+                *wb = Wb::Writer(reader::open_reader(&self.path)?);
+                Ok(())
+            }
+            Wb::Creater(ref mut book) => creater::add_sheet_creater(book, sheetname, index),
+            Wb::Reader(_) => unreachable!(),
+        }
+    }
 
-    fn is_writable(&self) {
-        assert_ne!(self.mode, Mode::Read, "Writable method not allowed in read-only mode");
+    // rename a sheet; the new name may differ from the current one in case only.
+    // In an existing workbook formulas and defined names referring to it follow the new name
+    pub fn rename_sheet(&self, cur_name: &str, new_name: &str) -> Result<(), ExcelError> {
+        let sheets = self.get_sheetnames()?;
+        if !sheets.iter().any(|s| s == cur_name) {
+            return Err(ExcelError::SheetNotFound(cur_name.to_string()));
+        }
+        sheetname::validate_sheet_name(new_name, sheets.iter().filter(|s| *s != cur_name))?;
+        let mut wb = self.wb();
+        match *wb {
+            Wb::Writer(_) => {
+                writer::rename_sheet_writer(&self.path, cur_name, new_name)?;
+                self.clear_cache();
+                *wb = Wb::Writer(reader::open_reader(&self.path)?);
+                Ok(())
+            }
+            Wb::Creater(ref mut book) => creater::rename_sheet_creater(book, cur_name, new_name),
+            Wb::Reader(_) => unreachable!(),
+        }
+    }
+
+    pub fn delete_sheet(&self, sheetname: &str) -> Result<(), ExcelError> {
+        let mut wb = self.wb();
+        match *wb {
+            Wb::Writer(_) => {
+                writer::delete_sheet_writer(&self.path, sheetname)?;
+                self.clear_cache();
+                *wb = Wb::Writer(reader::open_reader(&self.path)?);
+                Ok(())
+            }
+            Wb::Creater(ref mut book) => creater::delete_sheet_creater(book, sheetname),
+            Wb::Reader(_) => unreachable!(),
+        }
+    }
+
+    // reorder the sheets, `sheetnames` has to list every sheet exactly once
+    pub fn reorder_sheets(&self, sheetnames: &[&str]) -> Result<(), ExcelError> {
+        let mut sheets = self.get_sheetnames()?;
+        let mut order: Vec<_> = sheetnames.to_vec();
+        sheets.sort();
+        order.sort_unstable();
+        if sheets != order {
+            return Err(ExcelError::InvalidSheetOrder);
+        }
+        let mut wb = self.wb();
+        match *wb {
+            Wb::Writer(_) => {
+                writer::reorder_sheets_writer(&self.path, sheetnames)?;
+                *wb = Wb::Writer(reader::open_reader(&self.path)?);
+                Ok(())
+            }
+            Wb::Creater(ref mut book) => creater::reorder_sheets_creater(book, sheetnames),
+            Wb::Reader(_) => unreachable!(),
+        }
     }
 
-    pub fn set_range_values(&self, sheetname: &str) -> Result<(), Error> {
-        self.is_writable();
-        // if let Some(Ok(mut range)) = self.wb.worksheet_range(sheetname) {
-        //     range.set_value((5, 5), CellValue::Float(1.0));
-        // };
+    // write a created workbook to disk; the handle then continues in the same way as a ReadWrite one.
+    // ReadWrite handles save on every change, so there is nothing to do for them.
+    pub fn save(&self) -> Result<(), ExcelError> {
+        let mut wb = self.wb();
+        if let Wb::Creater(ref mut book) = *wb {
+            self.save_tried.store(true, Ordering::Relaxed);
+            creater::save_creater(book, &self.path)?;
+            *wb = Wb::Writer(reader::open_reader(&self.path)?);
+        }
         Ok(())
     }
 
+    // save and release the workbook
+    pub fn close(self) -> Result<(), ExcelError> {
+        self.save()
+    }
+
+}
+
+
+// Styling for created workbooks, until they are saved
+impl ExcelHandle<New> {
+    // register a style under a name, to be looked up with named_style
+    pub fn define_style(&self, name: &str, style: CellStyle) -> Result<(), ExcelError> {
+        self.with_book(|book| {
+            book.define_style(name, style);
+            Ok(())
+        })
+    }
+
+    pub fn named_style(&self, name: &str) -> Result<CellStyle, ExcelError> {
+        self.with_book(|book| book.named_style(name))
+    }
+
+    // style every cell of a range, with or without a value; a single cell with CellRange::from(address).
+    // Whole rows or columns ("3:5", "C:C") are styled as rows or columns, not cell by cell
+    pub fn set_style(&self, sheetname: &str, range: &CellRange, style: &CellStyle) -> Result<(), ExcelError> {
+        self.with_book(|book| creater::set_style_creater(book, sheetname, range, style))
+    }
+
+    // set_range_values giving the written cells a style
+    pub fn set_range_values_styled<T>(&self,
+            sheetname: &str,
+            row: u32,
+            col: u32,
+            data: &[Vec<T>],
+            style: &CellStyle
+        )
+        -> Result<(), ExcelError>
+        where
+            T: Clone + Into<CellInput>,
+    {
+        let data = data.iter().map(|values| inputs(values, DateSystem::V1900)).collect::<Result<Vec<_>, _>>()?;
+        self.with_book(|book| creater::set_range_values_creater(book, sheetname, row, col, &data, Some(style)))
+    }
+
+    // append_row giving the written cells a style
+    pub fn append_row_styled<T>(&self, sheetname: &str, values: &[T], style: &CellStyle) -> Result<(), ExcelError>
+        where
+            T: Clone + Into<CellInput>,
+    {
+        let values = inputs(values, DateSystem::V1900)?;
+        self.with_book(|book| creater::append_row_creater(book, sheetname, &values, Some(style)))
+    }
+
+    fn with_book<T, F>(&self, func: F) -> Result<T, ExcelError>
+        where
+            F: FnOnce(&mut creater::Book) -> Result<T, ExcelError>,
+    {
+        match *self.wb() {
+            Wb::Creater(ref mut book) => func(book),
+            _ => Err(ExcelError::AlreadySaved),
+        }
+    }
+}
+
+// created workbooks use the 1900 date system
+fn date_system(wb: &Wb) -> DateSystem {
+    match *wb {
+        Wb::Reader(ref r) | Wb::Writer(ref r) => reader::date_system_reader(r),
+        Wb::Creater(_) => DateSystem::V1900,
+    }
+}
+
+// cell values to write, with dates as serials of `system`
+fn inputs<T>(values: &[T], system: DateSystem) -> Result<Vec<CellInput>, ExcelError>
+    where
+        T: Clone + Into<CellInput>,
+{
+    values.iter().cloned().map(|value| value.into().resolve(system)).collect()
+}
+
+// auto-save a created workbook that was never saved explicitly.
+// Errors can't be returned from drop and are lost, call save() or close() to handle them.
+// A workbook whose save() or close() failed isn't saved again.
+impl<M> Drop for ExcelHandle<M> {
+    fn drop(&mut self) {
+        if *self.save_tried.get_mut() {
+            return;
+        }
+        if let Wb::Creater(ref mut book) = *self.wb.get_mut().unwrap_or_else(PoisonError::into_inner) {
+            let _ = creater::save_creater(book, &self.path);
+        }
+    }
 }
 
-// wrapper function
-pub fn is_writable<'a, F>(ex: &'a ExcelHandle, func: F)
-    -> impl Fn(&str) -> Result<(), Error> + '_ // anonymous lifetime
+// wrapper function, only accepts writable handles
+pub fn is_writable<'a, M, F>(ex: &'a ExcelHandle<M>, func: F)
+    -> impl Fn(&str) -> Result<(), ExcelError> + 'a
     where
-        F: Fn(&ExcelHandle, &str) -> Result<(), Error> + 'a // or just 'static
+        M: Writable,
+        F: Fn(&ExcelHandle<M>, &str) -> Result<(), ExcelError> + 'a // or just 'static
 {
-    move |x: &str| {
-        println!("Wrapper func OK");
-        func(ex, x)
+    move |x: &str| func(ex, x)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use calamine::Data;
+
+    use super::*;
+
+    fn temp_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("excelhandler-{}-{}.xlsx", std::process::id(), name));
+        let _ = std::fs::remove_file(&path);
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn find_sheets_par_finds_what_find_sheets_does() {
+        let path = temp_path("par");
+        let ex = ExcelHandle::<New>::new(path.clone()).unwrap();
+        for i in 0..10u32 {
+            let sheetname = format!("S{}", i);
+            ex.add_sheet(&sheetname, None).unwrap();
+            let value = if i % 3 == 0 { Data::from("total") } else { Data::Int(i64::from(i)) };
+            ex.set_range_values(&sheetname, i, 1, &[vec![value]]).unwrap();
+        }
+        ex.save().unwrap();
+
+        let scan = Scan::all();
+        let total = Predicate::eq("total");
+        let found = ex.find_sheets(&scan, &total).unwrap();
+        assert_eq!(found, vec!["S0", "S3", "S6", "S9"]);
+        assert_eq!(ex.find_sheets_par(&scan, &total).unwrap(), found);
+        assert_eq!(ex.find_sheets_par(&scan, &Predicate::gt(0.0)).unwrap(), ex.find_sheets(&scan, &Predicate::gt(0.0)).unwrap());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn compact_row_values_skip_empty_cells() {
+        let path = temp_path("compact");
+        let ex = ExcelHandle::<New>::new(path.clone()).unwrap();
+        let data = vec![
+            vec![Data::Int(1), Data::String("x".to_string())],
+            vec![Data::Empty],
+            vec![Data::Int(3)],
+        ];
+        ex.set_range_values("Sheet1", 0, 0, &data).unwrap();
+        ex.save().unwrap();
+
+        let scan = Scan::new(0..5, 0);
+        let never = |_: &Vec<CellValue>| false;
+        let values = ex.iterate_row_values("Sheet1", &scan, never).unwrap();
+        assert_eq!(values, vec![vec![Data::Float(1.0), Data::Empty, Data::Float(3.0), Data::Empty, Data::Empty]]);
+        let values = ex.iterate_row_values_compact("Sheet1", &scan, never).unwrap();
+        assert_eq!(values, vec![vec![Data::Float(1.0), Data::Float(3.0)]]);
+        std::fs::remove_file(&path).unwrap();
     }
 }
@@ -0,0 +1,65 @@
+use serde::{Deserialize, Serialize};
+use unicode_normalization::UnicodeNormalization;
+
+
+// How text is normalized before matching, for sheets that mix full-width and
+// half-width characters: NFKC turns "ｆｏｏ" into "foo" and "ｶﾅ" into "カナ",
+// then surrounding spaces (including the ideographic space U+3000) are trimmed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct Normalization {
+    fold_kana: bool,
+}
+
+impl Normalization {
+    pub fn nfkc() -> Self {
+        Self::default()
+    }
+
+    // also match hiragana against katakana ("かな" == "カナ")
+    pub fn fold_kana(mut self) -> Self {
+        self.fold_kana = true;
+        self
+    }
+
+    pub fn apply(&self, s: &str) -> String {
+        let nfkc: String = s.nfkc().collect();
+        let trimmed = nfkc.trim();
+        if self.fold_kana {
+            trimmed.chars().map(hiragana_to_katakana).collect()
+        } else {
+            trimmed.to_string()
+        }
+    }
+}
+
+// ぁ (U+3041) .. ゖ (U+3096) sit 0x60 below their katakana
+fn hiragana_to_katakana(c: char) -> char {
+    match c {
+        '\u{3041}'..='\u{3096}' => char::from_u32(c as u32 + 0x60).unwrap_or(c),
+        _ => c,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nfkc_unifies_widths_and_trims() {
+        let nfkc = Normalization::nfkc();
+        assert_eq!(nfkc.apply("ｆｏｏ１２３"), "foo123");
+        assert_eq!(nfkc.apply("ｶﾞｷﾞ"), "ガギ"); // voiced marks combine
+        assert_eq!(nfkc.apply("\u{3000} 売上 \u{3000}"), "売上");
+        assert_eq!(nfkc.apply("かな"), "かな");
+    }
+
+    #[test]
+    fn kana_folding_turns_hiragana_into_katakana() {
+        let folded = Normalization::nfkc().fold_kana();
+        assert_eq!(folded.apply("かな"), "カナ");
+        assert_eq!(folded.apply("ﾃﾞｰﾀ"), folded.apply("でーた"));
+        assert_eq!(folded.apply("ぁゖ"), "ァヶ"); // the ends of the block
+        assert_eq!(folded.apply("ゝ漢字abc"), "ゝ漢字abc"); // others are left as they are
+    }
+}
@@ -0,0 +1,569 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File};
+use std::io::{BufReader, Read, Write};
+use std::path::Path;
+use std::process;
+
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::{Reader, Writer, XmlVersion};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+use super::error::ExcelError;
+
+const WORKBOOK: &str = "xl/workbook.xml";
+const WORKBOOK_RELS: &str = "xl/_rels/workbook.xml.rels";
+const CONTENT_TYPES: &str = "[Content_Types].xml";
+// the children of <workbook> that come after <calcPr>
+const AFTER_CALC_PR: [&[u8]; 9] = [
+    b"oleSize", b"customWorkbookViews", b"pivotCaches", b"smartTagPr", b"smartTagTypes",
+    b"webPublishing", b"fileRecoveryPr", b"webPublishObjects", b"extLst",
+];
+
+
+// An .xlsx package opened for editing.
+// Parts are edited in memory; untouched parts are copied byte-for-byte on save.
+pub struct Package {
+    archive: ZipArchive<BufReader<File>>,
+    edited: BTreeMap<String, Option<Vec<u8>>>, // None when the part is removed
+}
+
+impl Package {
+    pub fn open(path: &str) -> Result<Self, ExcelError> {
+        let archive = ZipArchive::new(BufReader::new(File::open(path)?))?;
+        Ok(Self { archive, edited: BTreeMap::new() })
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        match self.edited.get(name) {
+            Some(part) => part.is_some(),
+            None => self.archive.index_for_name(name).is_some(),
+        }
+    }
+
+    pub fn read(&mut self, name: &str) -> Result<Vec<u8>, ExcelError> {
+        if let Some(part) = self.edited.get(name) {
+            return part.clone().ok_or(ExcelError::InvalidPackage("package part not found"));
+        }
+        let mut file = self.archive.by_name(name)?;
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf)?;
+        Ok(buf)
+    }
+
+    // the names of every part, edits included
+    pub fn part_names(&self) -> Vec<String> {
+        let mut names: BTreeSet<String> = self.archive.file_names().map(str::to_string).collect();
+        for (name, part) in &self.edited {
+            match part {
+                Some(_) => names.insert(name.clone()),
+                None => names.remove(name),
+            };
+        }
+        names.into_iter().collect()
+    }
+
+    pub fn write(&mut self, name: &str, data: Vec<u8>) {
+        self.edited.insert(name.to_string(), Some(data));
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.edited.insert(name.to_string(), None);
+    }
+
+    // write the package back to `path`. It is written next to it first and then moved over it,
+    // so that a failed write (a full disk, a crash) leaves the original workbook as it was.
+    pub fn save(mut self, path: &str) -> Result<(), ExcelError> {
+        let path = Path::new(path);
+        let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        let temp = path.with_file_name(format!(".~{}.{}.tmp", name, process::id()));
+        let result = self.write_to(&temp).and_then(|()| {
+            fs::set_permissions(&temp, fs::metadata(path)?.permissions())?;
+            drop(self.archive); // release the file before replacing it
+            Ok(fs::rename(&temp, path)?)
+        });
+        if result.is_err() {
+            let _ = fs::remove_file(&temp);
+        }
+        result
+    }
+
+    fn write_to(&mut self, temp: &Path) -> Result<(), ExcelError> {
+        let mut zip = ZipWriter::new(File::create(temp)?);
+        for i in 0..self.archive.len() {
+            let file = self.archive.by_index_raw(i)?;
+            match self.edited.remove(file.name()) {
+                Some(Some(data)) => {
+                    let options = SimpleFileOptions::default().compression_method(file.compression());
+                    zip.start_file(file.name(), options)?;
+                    zip.write_all(&data)?;
+                }
+                Some(None) => {}
+                None => zip.raw_copy_file(file)?,
+            }
+        }
+        for (name, data) in std::mem::take(&mut self.edited) {
+            if let Some(data) = data {
+                let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+                zip.start_file(name, options)?;
+                zip.write_all(&data)?;
+            }
+        }
+        zip.finish()?.sync_all()?;
+        Ok(())
+    }
+
+    // return the part name of a worksheet, e.g. "xl/worksheets/sheet1.xml"
+    pub fn sheet_part(&mut self, sheetname: &str) -> Result<String, ExcelError> {
+        let workbook = self.read(WORKBOOK)?;
+        let rid = find_attribute(&workbook, b"sheet", b"name", |v| v == sheetname, b"r:id")?
+            .ok_or_else(|| ExcelError::SheetNotFound(sheetname.to_string()))?;
+        let rels = self.read(WORKBOOK_RELS)?;
+        let target = find_attribute(&rels, b"Relationship", b"Id", |v| v == rid, b"Target")?
+            .ok_or(ExcelError::InvalidPackage("sheet relationship not found"))?;
+        Ok(part_name(&target))
+    }
+
+    // return the part name of a workbook-level part such as "sharedStrings" or "calcChain"
+    pub fn workbook_part(&mut self, kind: &str) -> Result<Option<String>, ExcelError> {
+        let suffix = format!("/{}", kind);
+        let rels = self.read(WORKBOOK_RELS)?;
+        let target = find_attribute(&rels, b"Relationship", b"Type", |v| v.ends_with(&suffix), b"Target")?;
+        Ok(target.map(|t| part_name(&t)))
+    }
+
+    // register a new workbook-level part in the workbook relationships and content types,
+    // returning its relationship id
+    pub fn add_workbook_part(&mut self, name: &str, kind: &str, content_type: &str) -> Result<String, ExcelError> {
+        let rels = self.read(WORKBOOK_RELS)?;
+        let mut ids = Vec::new();
+        let mut reader = Reader::from_reader(rels.as_slice());
+        loop {
+            match reader.read_event().map_err(xml_error)? {
+                Event::Start(ref e) | Event::Empty(ref e) if e.local_name().as_ref() == b"Relationship" => {
+                    if let Some(id) = attribute(e, b"Id")? {
+                        ids.push(id);
+                    }
+                }
+                Event::Eof => break,
+                _ => {}
+            }
+        }
+        let id = (1..).map(|n| format!("rId{}", n)).find(|id| !ids.contains(id)).unwrap_or_default();
+        let target = name.strip_prefix("xl/").map(str::to_string).unwrap_or(format!("/{}", name));
+        let relationship = BytesStart::new("Relationship").with_attributes(vec![
+            ("Id", id.as_str()),
+            ("Type", format!("http://schemas.openxmlformats.org/officeDocument/2006/relationships/{}", kind).as_str()),
+            ("Target", target.as_str()),
+        ]);
+        let rels = append_child(&rels, b"Relationships", relationship)?;
+        self.write(WORKBOOK_RELS, rels);
+
+        let types = self.read(CONTENT_TYPES)?;
+        let part_name = format!("/{}", name);
+        let entry = BytesStart::new("Override").with_attributes(vec![
+            ("PartName", part_name.as_str()),
+            ("ContentType", content_type),
+        ]);
+        let types = append_child(&types, b"Types", entry)?;
+        self.write(CONTENT_TYPES, types);
+        Ok(id)
+    }
+
+    // drop a workbook-level part together with its relationship and content type
+    pub fn remove_workbook_part(&mut self, name: &str) -> Result<(), ExcelError> {
+        let target = name.strip_prefix("xl/").unwrap_or(name).to_string();
+        let absolute = format!("/{}", name);
+        let rels = self.read(WORKBOOK_RELS)?;
+        let rels = remove_elements(&rels, b"Relationship", b"Target", |v| v == target || v == absolute)?;
+        self.write(WORKBOOK_RELS, rels);
+        self.remove_part(name)
+    }
+
+    // drop a part together with its own relationships and its content type
+    pub fn remove_part(&mut self, name: &str) -> Result<(), ExcelError> {
+        let rels = rels_name(name);
+        if self.contains(&rels) {
+            self.remove(&rels);
+        }
+        let absolute = format!("/{}", name);
+        let types = self.read(CONTENT_TYPES)?;
+        let types = remove_elements(&types, b"Override", b"PartName", |v| v == absolute)?;
+        self.write(CONTENT_TYPES, types);
+        self.remove(name);
+        Ok(())
+    }
+
+    // the parts `name` refers to through its relationships, external links left out
+    pub fn related_parts(&mut self, name: &str) -> Result<Vec<String>, ExcelError> {
+        let rels = rels_name(name);
+        if !self.contains(&rels) {
+            return Ok(Vec::new());
+        }
+        self.targets(&rels)
+    }
+
+    // every part some relationship of the package refers to
+    pub fn linked_parts(&mut self) -> Result<BTreeSet<String>, ExcelError> {
+        let mut linked = BTreeSet::new();
+        for rels in self.part_names().into_iter().filter(|name| name.ends_with(".rels")) {
+            linked.extend(self.targets(&rels)?);
+        }
+        Ok(linked)
+    }
+
+    fn targets(&mut self, rels: &str) -> Result<Vec<String>, ExcelError> {
+        let dir = rels.rfind("_rels/").map_or("", |n| rels[..n].trim_end_matches('/'));
+        let xml = self.read(rels)?;
+        let mut reader = Reader::from_reader(xml.as_slice());
+        let mut targets = Vec::new();
+        loop {
+            match reader.read_event().map_err(xml_error)? {
+                Event::Start(ref e) | Event::Empty(ref e) if e.local_name().as_ref() == b"Relationship" => {
+                    if attribute(e, b"TargetMode")?.as_deref() == Some("External") {
+                        continue;
+                    }
+                    if let Some(target) = attribute(e, b"Target")? {
+                        targets.push(resolve(dir, &target));
+                    }
+                }
+                Event::Eof => break,
+                _ => {}
+            }
+        }
+        Ok(targets)
+    }
+
+    // the <sheet> elements of the workbook, in tab order
+    pub fn sheets(&mut self) -> Result<Vec<BytesStart<'static>>, ExcelError> {
+        let workbook = self.read(WORKBOOK)?;
+        let mut reader = Reader::from_reader(workbook.as_slice());
+        let mut sheets = Vec::new();
+        loop {
+            match reader.read_event().map_err(xml_error)? {
+                Event::Start(ref e) | Event::Empty(ref e) if e.local_name().as_ref() == b"sheet" => {
+                    sheets.push(e.to_owned());
+                }
+                Event::Eof => break,
+                _ => {}
+            }
+        }
+        Ok(sheets)
+    }
+
+    // replace the <sheet> elements of the workbook. `moved[i]` is the new position of the
+    // sheet that was at i, None if it was deleted; sheet-local names and the active tab follow it.
+    pub fn set_sheets(&mut self, sheets: &[BytesStart], moved: &[Option<usize>]) -> Result<(), ExcelError> {
+        let position = |e: &BytesStart, name: &[u8]| -> Result<Option<Option<usize>>, ExcelError> {
+            Ok(attribute(e, name)?.map(|i| i.parse::<usize>().ok().and_then(|i| moved.get(i).cloned().flatten())))
+        };
+        let workbook = self.read(WORKBOOK)?;
+        let mut reader = Reader::from_reader(workbook.as_slice());
+        let mut writer = Writer::new(Vec::with_capacity(workbook.len()));
+        let mut skip = 0;
+        loop {
+            let event = reader.read_event().map_err(xml_error)?;
+            if skip > 0 {
+                match event {
+                    Event::Start(_) => skip += 1,
+                    Event::End(_) => skip -= 1,
+                    Event::Eof => break,
+                    _ => {}
+                }
+                continue;
+            }
+            match event {
+                Event::Start(ref e) | Event::Empty(ref e) if e.local_name().as_ref() == b"sheets" => {
+                    writer.write_event(Event::Start(e.borrow()))?;
+                    for sheet in sheets {
+                        writer.write_event(Event::Empty(sheet.borrow()))?;
+                    }
+                    writer.write_event(Event::End(e.to_end()))?;
+                    if let Event::Start(_) = event {
+                        skip = 1;
+                    }
+                }
+                Event::Start(ref e) if e.local_name().as_ref() == b"definedName" => {
+                    match position(e, b"localSheetId")? {
+                        Some(Some(i)) => {
+                            let start = replace_attribute(e, "localSheetId", &i.to_string());
+                            writer.write_event(Event::Start(start))?;
+                        }
+                        Some(None) => skip = 1, // local to a deleted sheet
+                        None => writer.write_event(event.borrow())?,
+                    }
+                }
+                Event::Start(ref e) | Event::Empty(ref e) if e.local_name().as_ref() == b"workbookView" => {
+                    let mut view = e.to_owned();
+                    for name in &["activeTab", "firstSheet"] {
+                        if let Some(i) = position(&view, name.as_bytes())? {
+                            view = replace_attribute(&view, name, &i.unwrap_or(0).to_string());
+                        }
+                    }
+                    match event {
+                        Event::Start(_) => writer.write_event(Event::Start(view))?,
+                        _ => writer.write_event(Event::Empty(view))?,
+                    }
+                }
+                Event::Eof => break,
+                e => writer.write_event(e)?,
+            }
+        }
+        self.write(WORKBOOK, writer.into_inner());
+        Ok(())
+    }
+
+    // ask Excel to recalculate every formula when the file is next opened
+    pub fn set_full_calc_on_load(&mut self) -> Result<(), ExcelError> {
+        let workbook = self.read(WORKBOOK)?;
+        self.write(WORKBOOK, full_calc_on_load(&workbook)?);
+        Ok(())
+    }
+}
+
+// workbook.xml with fullCalcOnLoad set on its <calcPr>, added if it has none
+fn full_calc_on_load(workbook: &[u8]) -> Result<Vec<u8>, ExcelError> {
+    if contains_element(workbook, b"calcPr")? {
+        return set_attribute(workbook, b"calcPr", "fullCalcOnLoad", "1");
+    }
+    let mut calc_pr = BytesStart::new("calcPr");
+    calc_pr.push_attribute(("fullCalcOnLoad", "1"));
+    insert_child(workbook, b"workbook", calc_pr, &AFTER_CALC_PR)
+}
+
+// relationship target (relative to xl/ or absolute) -> part name
+fn part_name(target: &str) -> String {
+    match target.strip_prefix('/') {
+        Some(absolute) => absolute.to_string(),
+        None => format!("xl/{}", target),
+    }
+}
+
+
+// the relationships part of a part, e.g. "xl/worksheets/_rels/sheet1.xml.rels"
+fn rels_name(name: &str) -> String {
+    match name.rfind('/') {
+        Some(n) => format!("{}/_rels/{}.rels", &name[..n], &name[n + 1..]),
+        None => format!("_rels/{}.rels", name),
+    }
+}
+
+// relationship target of a part in `dir` -> part name,
+// e.g. ("xl/worksheets", "../drawings/drawing1.xml") -> "xl/drawings/drawing1.xml"
+fn resolve(dir: &str, target: &str) -> String {
+    if let Some(absolute) = target.strip_prefix('/') {
+        return absolute.to_string();
+    }
+    let mut segments: Vec<&str> = dir.split('/').filter(|s| !s.is_empty()).collect();
+    for segment in target.split('/') {
+        match segment {
+            ".." => {
+                segments.pop();
+            }
+            "." | "" => {}
+            segment => segments.push(segment),
+        }
+    }
+    segments.join("/")
+}
+
+// value of attribute `want` on the first `tag` element whose `key` attribute matches
+pub fn find_attribute<F>(xml: &[u8], tag: &[u8], key: &[u8], matches: F, want: &[u8])
+    -> Result<Option<String>, ExcelError>
+    where
+        F: Fn(&str) -> bool,
+{
+    let mut reader = Reader::from_reader(xml);
+    loop {
+        match reader.read_event().map_err(xml_error)? {
+            Event::Start(ref e) | Event::Empty(ref e)
+                if e.local_name().as_ref() == tag && attribute(e, key)?.as_deref().is_some_and(&matches) => {
+                return attribute(e, want);
+            }
+            Event::Eof => return Ok(None),
+            _ => {}
+        }
+    }
+}
+
+pub fn attribute(e: &BytesStart, name: &[u8]) -> Result<Option<String>, ExcelError> {
+    match e.try_get_attribute(name).map_err(xml_error)? {
+        Some(attr) => Ok(Some(attr.normalized_value(XmlVersion::Implicit1_0).map_err(xml_error)?.into_owned())),
+        None => Ok(None),
+    }
+}
+
+pub fn without_attribute(e: &BytesStart, name: &[u8]) -> BytesStart<'static> {
+    let mut start = e.to_owned();
+    start.clear_attributes();
+    start.extend_attributes(e.attributes().flatten().filter(|a| a.key.as_ref() != name));
+    start
+}
+
+pub fn replace_attribute(e: &BytesStart, name: &str, value: &str) -> BytesStart<'static> {
+    let mut start = without_attribute(e, name.as_bytes());
+    start.push_attribute((name, value));
+    start
+}
+
+fn contains_element(xml: &[u8], tag: &[u8]) -> Result<bool, ExcelError> {
+    let mut reader = Reader::from_reader(xml);
+    loop {
+        match reader.read_event().map_err(xml_error)? {
+            Event::Start(ref e) | Event::Empty(ref e) if e.local_name().as_ref() == tag => return Ok(true),
+            Event::Eof => return Ok(false),
+            _ => {}
+        }
+    }
+}
+
+// set an attribute on the first `tag` element, keeping the others as they are
+pub fn set_attribute(xml: &[u8], tag: &[u8], name: &str, value: &str) -> Result<Vec<u8>, ExcelError> {
+    let mut reader = Reader::from_reader(xml);
+    let mut writer = Writer::new(Vec::with_capacity(xml.len()));
+    let mut done = false;
+    loop {
+        match reader.read_event().map_err(xml_error)? {
+            Event::Start(ref e) if !done && e.local_name().as_ref() == tag => {
+                writer.write_event(Event::Start(replace_attribute(e, name, value)))?;
+                done = true;
+            }
+            Event::Empty(ref e) if !done && e.local_name().as_ref() == tag => {
+                writer.write_event(Event::Empty(replace_attribute(e, name, value)))?;
+                done = true;
+            }
+            Event::Eof => break,
+            e => writer.write_event(e)?,
+        }
+    }
+    Ok(writer.into_inner())
+}
+
+// remove every `tag` element whose `key` attribute matches
+fn remove_elements<F>(xml: &[u8], tag: &[u8], key: &[u8], matches: F) -> Result<Vec<u8>, ExcelError>
+    where
+        F: Fn(&str) -> bool,
+{
+    let mut reader = Reader::from_reader(xml);
+    let mut writer = Writer::new(Vec::with_capacity(xml.len()));
+    let mut skip = 0;
+    loop {
+        let event = reader.read_event().map_err(xml_error)?;
+        if skip > 0 {
+            match event {
+                Event::Start(_) => skip += 1,
+                Event::End(_) => skip -= 1,
+                Event::Eof => break,
+                _ => {}
+            }
+            continue;
+        }
+        match event {
+            Event::Start(ref e) | Event::Empty(ref e)
+                if e.local_name().as_ref() == tag && attribute(e, key)?.as_deref().is_some_and(&matches) => {
+                if let Event::Start(_) = event {
+                    skip = 1;
+                }
+            }
+            Event::Eof => break,
+            e => writer.write_event(e)?,
+        }
+    }
+    Ok(writer.into_inner())
+}
+
+// append an empty child element at the end of `parent`
+fn append_child(xml: &[u8], parent: &[u8], child: BytesStart) -> Result<Vec<u8>, ExcelError> {
+    insert_child(xml, parent, child, &[])
+}
+
+// insert an empty child element into the first `parent`, before the first of its children
+// named in `followers` (those the schema puts after it) or else at the end.
+// The child takes the parent's namespace prefix.
+fn insert_child(xml: &[u8], parent: &[u8], mut child: BytesStart, followers: &[&[u8]])
+    -> Result<Vec<u8>, ExcelError>
+{
+    let mut reader = Reader::from_reader(xml);
+    let mut writer = Writer::new(Vec::with_capacity(xml.len()));
+    let mut depth = 0; // within `parent`
+    let mut done = false;
+    loop {
+        let event = reader.read_event().map_err(xml_error)?;
+        match event {
+            Event::Empty(ref e) if !done && depth == 0 && e.local_name().as_ref() == parent => {
+                child = with_prefix_of(&child, e);
+                writer.write_event(Event::Start(e.borrow()))?;
+                writer.write_event(Event::Empty(child.borrow()))?;
+                writer.write_event(Event::End(e.to_end()))?;
+                done = true;
+                continue;
+            }
+            Event::Start(ref e) | Event::Empty(ref e)
+                if !done && depth == 1 && followers.contains(&e.local_name().as_ref()) => {
+                writer.write_event(Event::Empty(child.borrow()))?;
+                done = true;
+            }
+            Event::End(_) if !done && depth == 1 => {
+                writer.write_event(Event::Empty(child.borrow()))?;
+                done = true;
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+        match event {
+            Event::Start(ref e) if depth == 0 && !done && e.local_name().as_ref() == parent => {
+                child = with_prefix_of(&child, e);
+                depth += 1;
+            }
+            Event::Start(_) if depth > 0 => depth += 1,
+            Event::End(_) if depth > 0 => depth -= 1,
+            _ => {}
+        }
+        writer.write_event(event)?;
+    }
+    Ok(writer.into_inner())
+}
+
+// `child` named with the namespace prefix of `parent`, as <x:calcPr/> in <x:workbook>
+fn with_prefix_of(child: &BytesStart, parent: &BytesStart) -> BytesStart<'static> {
+    let prefix = match parent.name().prefix() {
+        Some(prefix) => String::from_utf8_lossy(prefix.as_ref()).into_owned(),
+        None => return child.to_owned(),
+    };
+    let name = format!("{}:{}", prefix, String::from_utf8_lossy(child.local_name().as_ref()));
+    let mut start = BytesStart::new(name);
+    start.extend_attributes(child.attributes().flatten());
+    start
+}
+
+pub fn xml_error<E: Into<quick_xml::Error>>(e: E) -> ExcelError {
+    ExcelError::Xml(e.into())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn calc(workbook: &str) -> String {
+        String::from_utf8(full_calc_on_load(workbook.as_bytes()).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn full_calc_on_load_adds_calc_pr_where_the_schema_wants_it() {
+        assert_eq!(calc("<workbook><sheets/><definedNames/></workbook>"),
+                   r#"<workbook><sheets/><definedNames/><calcPr fullCalcOnLoad="1"/></workbook>"#);
+        assert_eq!(calc("<x:workbook><x:sheets/><x:pivotCaches><x:pivotCache/></x:pivotCaches><x:extLst/></x:workbook>"),
+                   r#"<x:workbook><x:sheets/><x:calcPr fullCalcOnLoad="1"/><x:pivotCaches><x:pivotCache/></x:pivotCaches><x:extLst/></x:workbook>"#);
+        assert_eq!(calc("<workbook/>"), r#"<workbook><calcPr fullCalcOnLoad="1"/></workbook>"#);
+        assert_eq!(calc("<x:workbook/>"), r#"<x:workbook><x:calcPr fullCalcOnLoad="1"/></x:workbook>"#);
+    }
+
+    #[test]
+    fn full_calc_on_load_keeps_an_existing_calc_pr() {
+        assert_eq!(calc(r#"<workbook><sheets/><calcPr calcId="191029"/><extLst/></workbook>"#),
+                   r#"<workbook><sheets/><calcPr calcId="191029" fullCalcOnLoad="1"/><extLst/></workbook>"#);
+        assert_eq!(calc(r#"<workbook><calcPr fullCalcOnLoad="0"></calcPr></workbook>"#),
+                   r#"<workbook><calcPr fullCalcOnLoad="1"></calcPr></workbook>"#);
+    }
+}
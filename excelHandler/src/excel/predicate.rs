@@ -0,0 +1,358 @@
+use std::convert::TryFrom;
+
+use calamine::{Data, DataType};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use super::error::ExcelError;
+use super::fuzzy::fuzzy_match;
+use super::normalize::Normalization;
+
+
+// Anything that can decide whether a cell matches: closures and Predicate
+pub trait CellPredicate {
+    fn matches(&self, value: &Data) -> bool;
+}
+
+impl<F: Fn(&Data) -> bool> CellPredicate for F {
+    fn matches(&self, value: &Data) -> bool {
+        self(value)
+    }
+}
+
+// Stop condition of iterate_row_values, given the values of one column (or row)
+pub trait RowPredicate {
+    #[allow(clippy::ptr_arg)] // same argument as the closures
+    fn matches_row(&self, values: &Vec<Data>) -> bool;
+}
+
+impl<F: Fn(&Vec<Data>) -> bool> RowPredicate for F {
+    fn matches_row(&self, values: &Vec<Data>) -> bool {
+        self(values)
+    }
+}
+
+
+// A cell condition built from data rather than code, so common searches are one-liners:
+//   Predicate::eq("foo")
+//   Predicate::contains("total").or(Predicate::between(0.0, 1.0)).not()
+//   Predicate::eq("ｶﾅ").normalized(Normalization::nfkc().fold_kana())
+// Text conditions only match string cells and numeric ones only int and float cells.
+// Predicates serialize with serde, so searches can be kept in configuration files.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Predicate {
+    Eq(#[serde(with = "cell_value")] Data), // numbers match by value, int or float
+    EqIgnoreCase(String),
+    Contains(String),
+    StartsWith(String),
+    Fuzzy(String, usize), // at most this many edits away
+    Regex(Pattern),
+    Gt(f64),
+    Lt(f64),
+    Between(f64, f64), // both ends included
+    IsEmpty,
+    IsError,
+    IsDate,
+    And(Vec<Predicate>),
+    Or(Vec<Predicate>),
+    Not(Box<Predicate>),
+    Normalized(Normalization, Box<Predicate>), // text is normalized on both sides
+}
+
+impl Predicate {
+    pub fn eq(value: impl Into<Data>) -> Self {
+        Predicate::Eq(value.into())
+    }
+
+    pub fn eq_ignore_case(s: &str) -> Self {
+        Predicate::EqIgnoreCase(s.to_lowercase())
+    }
+
+    pub fn contains(s: &str) -> Self {
+        Predicate::Contains(s.to_string())
+    }
+
+    pub fn starts_with(s: &str) -> Self {
+        Predicate::StartsWith(s.to_string())
+    }
+
+    // text within `max_distance` typos of `s`, e.g. "Adress" for "Address"
+    pub fn fuzzy(s: &str, max_distance: usize) -> Self {
+        Predicate::Fuzzy(s.to_string(), max_distance)
+    }
+
+    pub fn regex(pattern: &str) -> Result<Self, ExcelError> {
+        Ok(Predicate::Regex(Pattern::new(pattern)?))
+    }
+
+    pub fn gt(n: f64) -> Self {
+        Predicate::Gt(n)
+    }
+
+    pub fn lt(n: f64) -> Self {
+        Predicate::Lt(n)
+    }
+
+    pub fn between(low: f64, high: f64) -> Self {
+        Predicate::Between(low, high)
+    }
+
+    pub fn is_empty() -> Self {
+        Predicate::IsEmpty
+    }
+
+    pub fn is_error() -> Self {
+        Predicate::IsError
+    }
+
+    // date and time cells, including ISO 8601 strings from .ods files
+    pub fn is_date() -> Self {
+        Predicate::IsDate
+    }
+
+    pub fn and(self, other: Predicate) -> Self {
+        match self {
+            Predicate::And(mut all) => {
+                all.push(other);
+                Predicate::And(all)
+            }
+            p => Predicate::And(vec![p, other]),
+        }
+    }
+
+    pub fn or(self, other: Predicate) -> Self {
+        match self {
+            Predicate::Or(mut any) => {
+                any.push(other);
+                Predicate::Or(any)
+            }
+            p => Predicate::Or(vec![p, other]),
+        }
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn not(self) -> Self {
+        match self {
+            Predicate::Not(p) => *p,
+            p => Predicate::Not(Box::new(p)),
+        }
+    }
+
+    // match string cells after normalizing them and the text of this predicate,
+    // so "foo" also finds "ｆｏｏ" and "foo\u{3000}". Regex patterns are left as written.
+    pub fn normalized(self, normalization: Normalization) -> Self {
+        Predicate::Normalized(normalization, Box::new(self.normalize_text(&normalization)))
+    }
+
+    fn normalize_text(self, n: &Normalization) -> Self {
+        match self {
+            Predicate::Eq(Data::String(s)) => Predicate::Eq(Data::String(n.apply(&s))),
+            Predicate::EqIgnoreCase(s) => Predicate::EqIgnoreCase(n.apply(&s).to_lowercase()),
+            Predicate::Contains(s) => Predicate::Contains(n.apply(&s)),
+            Predicate::StartsWith(s) => Predicate::StartsWith(n.apply(&s)),
+            Predicate::Fuzzy(s, max) => Predicate::Fuzzy(n.apply(&s), max),
+            Predicate::And(all) => Predicate::And(all.into_iter().map(|p| p.normalize_text(n)).collect()),
+            Predicate::Or(any) => Predicate::Or(any.into_iter().map(|p| p.normalize_text(n)).collect()),
+            Predicate::Not(p) => Predicate::Not(Box::new(p.normalize_text(n))),
+            p => p,
+        }
+    }
+}
+
+impl CellPredicate for Predicate {
+    fn matches(&self, value: &Data) -> bool {
+        match self {
+            Predicate::Eq(expected) => equals(value, expected),
+            Predicate::EqIgnoreCase(s) => text(value).is_some_and(|t| t.to_lowercase() == *s),
+            Predicate::Contains(s) => text(value).is_some_and(|t| t.contains(s.as_str())),
+            Predicate::StartsWith(s) => text(value).is_some_and(|t| t.starts_with(s.as_str())),
+            Predicate::Fuzzy(s, max) => text(value).is_some_and(|t| fuzzy_match(t, s, *max).is_some()),
+            Predicate::Regex(pattern) => text(value).is_some_and(|t| pattern.regex.is_match(t)),
+            Predicate::Gt(n) => number(value).is_some_and(|x| x > *n),
+            Predicate::Lt(n) => number(value).is_some_and(|x| x < *n),
+            Predicate::Between(low, high) => number(value).is_some_and(|x| *low <= x && x <= *high),
+            Predicate::IsEmpty => value.is_empty(),
+            Predicate::IsError => value.is_error(),
+            Predicate::IsDate => value.is_datetime() || value.is_datetime_iso(),
+            Predicate::And(all) => all.iter().all(|p| p.matches(value)),
+            Predicate::Or(any) => any.iter().any(|p| p.matches(value)),
+            Predicate::Not(p) => !p.matches(value),
+            Predicate::Normalized(n, p) => match value {
+                Data::String(s) => p.matches(&Data::String(n.apply(s))),
+                _ => p.matches(value),
+            },
+        }
+    }
+}
+
+// so one Predicate can be reused across calls
+impl CellPredicate for &Predicate {
+    fn matches(&self, value: &Data) -> bool {
+        (*self).matches(value)
+    }
+}
+
+// a row stops when every one of its cells matches, e.g. Predicate::is_empty()
+impl RowPredicate for Predicate {
+    fn matches_row(&self, values: &Vec<Data>) -> bool {
+        values.iter().all(|value| self.matches(value))
+    }
+}
+
+// A regular expression that serializes as its pattern and is compiled again when deserialized
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Pattern {
+    regex: Regex,
+}
+
+impl Pattern {
+    pub fn new(pattern: &str) -> Result<Self, ExcelError> {
+        Ok(Self { regex: Regex::new(pattern)? })
+    }
+
+    pub fn as_str(&self) -> &str {
+        self.regex.as_str()
+    }
+}
+
+impl TryFrom<String> for Pattern {
+    type Error = regex::Error;
+
+    fn try_from(pattern: String) -> Result<Self, regex::Error> {
+        Ok(Self { regex: Regex::new(&pattern)? })
+    }
+}
+
+impl From<Pattern> for String {
+    fn from(pattern: Pattern) -> Self {
+        pattern.as_str().to_string()
+    }
+}
+
+// Data has no Serialize, so Eq stores its value through this mirror of it
+mod cell_value {
+    use calamine::{CellErrorType, Data, ExcelDateTime, ExcelDateTimeType};
+    use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Serialize, Deserialize)]
+    enum Value {
+        Int(i64),
+        Float(f64),
+        String(String),
+        Bool(bool),
+        DateTime { serial: f64, duration: bool },
+        DateTimeIso(String),
+        DurationIso(String),
+        Error(String), // as Excel shows it, "#N/A"
+        Empty,
+    }
+
+    pub fn serialize<S: Serializer>(data: &Data, serializer: S) -> Result<S::Ok, S::Error> {
+        let value = match data {
+            Data::Int(i) => Value::Int(*i),
+            Data::Float(f) => Value::Float(*f),
+            Data::String(s) => Value::String(s.clone()),
+            Data::Bool(b) => Value::Bool(*b),
+            Data::DateTime(d) => Value::DateTime { serial: d.as_f64(), duration: d.is_duration() },
+            Data::DateTimeIso(s) => Value::DateTimeIso(s.clone()),
+            Data::DurationIso(s) => Value::DurationIso(s.clone()),
+            Data::Error(e) => Value::Error(e.to_string()),
+            Data::Empty => Value::Empty,
+        };
+        value.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Data, D::Error> {
+        Ok(match Value::deserialize(deserializer)? {
+            Value::Int(i) => Data::Int(i),
+            Value::Float(f) => Data::Float(f),
+            Value::String(s) => Data::String(s),
+            Value::Bool(b) => Data::Bool(b),
+            Value::DateTime { serial, duration } => {
+                let kind = if duration { ExcelDateTimeType::TimeDelta } else { ExcelDateTimeType::DateTime };
+                Data::DateTime(ExcelDateTime::new(serial, kind, false))
+            }
+            Value::DateTimeIso(s) => Data::DateTimeIso(s),
+            Value::DurationIso(s) => Data::DurationIso(s),
+            Value::Error(e) => match e.as_str() {
+                "#DATA!" => Data::Error(CellErrorType::GettingData),
+                _ => Data::Error(e.parse().map_err(|_| de::Error::custom(format!("unknown cell error {}", e)))?),
+            },
+            Value::Empty => Data::Empty,
+        })
+    }
+}
+
+// numbers by value, so eq(5i64) finds 5.0, and dates by serial whatever their 1904 flag
+fn equals(value: &Data, expected: &Data) -> bool {
+    match (value, expected) {
+        (Data::Int(a), Data::Int(b)) => a == b,
+        (Data::DateTime(a), Data::DateTime(b)) => a.as_f64() == b.as_f64() && a.is_duration() == b.is_duration(),
+        _ => match (number(value), number(expected)) {
+            (Some(a), Some(b)) => a == b,
+            _ => value == expected,
+        },
+    }
+}
+
+fn text(value: &Data) -> Option<&str> {
+    match value {
+        Data::String(s) => Some(s),
+        _ => None,
+    }
+}
+
+fn number(value: &Data) -> Option<f64> {
+    match *value {
+        Data::Int(i) => Some(i as f64),
+        Data::Float(f) => Some(f),
+        _ => None,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use calamine::{CellErrorType, ExcelDateTime, ExcelDateTimeType};
+
+    use super::*;
+
+    #[test]
+    fn eq_compares_numbers_by_value() {
+        assert!(Predicate::eq(5i64).matches(&Data::Float(5.0)));
+        assert!(Predicate::eq(5.0).matches(&Data::Int(5)));
+        assert!(!Predicate::eq(5i64).matches(&Data::Float(5.5)));
+        assert!(!Predicate::eq(5i64).matches(&Data::String("5".to_string())));
+        assert!(Predicate::eq(i64::MAX).matches(&Data::Int(i64::MAX)));
+        assert!(!Predicate::eq(i64::MAX).matches(&Data::Int(i64::MAX - 1)));
+        assert!(Predicate::eq("foo").matches(&Data::String("foo".to_string())));
+    }
+
+    #[test]
+    fn predicates_round_trip_through_serde() {
+        let date = Data::DateTime(ExcelDateTime::new(45000.5, ExcelDateTimeType::DateTime, false));
+        let predicate = Predicate::regex("^[a-z]+$").unwrap()
+            .or(Predicate::eq(5i64))
+            .or(Predicate::eq(date.clone()))
+            .or(Predicate::eq(Data::Error(CellErrorType::NA)))
+            .or(Predicate::between(0.0, 1.0).not())
+            .or(Predicate::contains("ｶﾅ").normalized(Normalization::nfkc().fold_kana()));
+        let json = serde_json::to_string(&predicate).unwrap();
+        assert!(json.contains(r#"{"Regex":"^[a-z]+$"}"#), "{}", json);
+        let loaded: Predicate = serde_json::from_str(&json).unwrap();
+        assert_eq!(serde_json::to_string(&loaded).unwrap(), json);
+
+        for value in &[Data::String("abc".to_string()), Data::Float(5.0), date, Data::Error(CellErrorType::NA),
+                       Data::Float(2.0), Data::String("カナ".to_string())] {
+            assert!(loaded.matches(value), "{:?}", value);
+        }
+        assert!(!loaded.matches(&Data::Float(0.5)));
+    }
+
+    #[test]
+    fn invalid_patterns_fail_to_load() {
+        assert!(serde_json::from_str::<Predicate>(r#"{"Regex":"(unclosed"}"#).is_err());
+        assert!(serde_json::from_str::<Predicate>(r##"{"Eq":{"Error":"#OOPS!"}}"##).is_err());
+    }
+}
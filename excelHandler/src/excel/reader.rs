@@ -1,62 +1,327 @@
 use std::fs::File;
 use std::io::BufReader;
+use std::sync::Arc;
 
-use calamine::{Reader, Xlsx, DataType};
+use calamine::{Reader, Range, Sheets, Data, open_workbook_auto};
+use serde::de::DeserializeOwned;
 
-pub type CellValue = DataType;
-pub type XlsxReader = Xlsx<BufReader<File>>;
+use super::cache::SheetCache;
+use super::address::{Address, MAX_COLS, MAX_ROWS};
+use super::dates::DateSystem;
+use super::de::deserialize_rows;
+use super::error::ExcelError;
+use super::fuzzy::{fuzzy_match, rank, FuzzyMatch};
+use super::predicate::{CellPredicate, RowPredicate};
+use super::scan::{limits, Indices, Order, Scan};
 
+pub type CellValue = Data;
+pub type ExcelReader = Sheets<BufReader<File>>; // .xlsx, .xlsm, .xlsb, .xls or .ods
 
-pub fn find_cell_reader<I, J, F>(r: &mut XlsxReader,
-        sheetname: &str,
-        rows: &impl Fn() -> I,
-        cols: &impl Fn() -> J,
-        func: F
-    )
-    -> Option<(u32, u32)> 
+
+// open any format calamine supports, by extension or by sniffing the content
+pub fn open_reader(path: &str) -> Result<ExcelReader, ExcelError> {
+    Ok(open_workbook_auto(path)?)
+}
+
+// .ods files store real dates, their numbers are read as 1900 serials
+pub fn date_system_reader(r: &ExcelReader) -> DateSystem {
+    let is_1904 = match *r {
+        Sheets::Xlsx(ref xlsx) => xlsx.has_1904_epoch(),
+        Sheets::Xlsb(ref xlsb) => xlsb.has_1904_epoch(),
+        Sheets::Xls(ref xls) => xls.has_1904_epoch(),
+        Sheets::Ods(_) => false,
+    };
+    if is_1904 { DateSystem::V1904 } else { DateSystem::V1900 }
+}
+
+fn worksheet_range(r: &mut ExcelReader, sheetname: &str) -> Result<Range<Data>, ExcelError> {
+    if !r.sheet_names().iter().any(|s| s == sheetname) {
+        return Err(ExcelError::SheetNotFound(sheetname.to_string()));
+    }
+    Ok(r.worksheet_range(sheetname)?)
+}
+
+
+// The parsed cells of a sheet
+pub struct Worksheet {
+    range: Arc<Range<Data>>, // shared with the handle's cache
+    clamp: bool,
+}
+
+// Iterator returned by Worksheet::iter_row_values
+pub struct RowValues<'a, F> {
+    range: &'a Range<Data>,
+    outer: Box<dyn Iterator<Item=u32> + 'a>,
+    inner: &'a Indices,
+    inner_end: Option<u32>,
+    order: Order,
+    func: F,
+    compact: bool,
+    done: bool,
+}
+
+impl Worksheet {
+    pub fn empty() -> Self {
+        Self { range: Arc::new(Range::empty()), clamp: true }
+    }
+
+    // whether open-ended rows and cols stop at the end of the used range (the default)
+    pub fn clamp_to_used_range(mut self, clamp: bool) -> Self {
+        self.clamp = clamp;
+        self
+    }
+
+    // bottom-right cell of the used range, None for a sheet without cells
+    pub fn last_cell(&self) -> Option<Address> {
+        self.range.end().map(Address::from)
+    }
+
+    // last (row, col) of the used range when clamping, see scan::limits()
+    fn used_end(&self) -> Option<Option<(u32, u32)>> {
+        if self.clamp { Some(self.range.end()) } else { None }
+    }
+
+    // return cell absolute address, searching row by row unless the scan says otherwise
+    pub fn find_cell<F>(&self, scan: &Scan, func: F) -> Option<(u32, u32)>
+        where
+            F: CellPredicate,
+    {
+        scan.cells(scan.order_or(Order::RowMajor), self.used_end())
+            .find(|&cell| self.range.get_value(cell).is_some_and(|value| func.matches(value)))
+    }
+
+    // return every matching cell address, in scan order
+    pub fn find_all_cells<F>(&self, scan: &Scan, func: F) -> Vec<(u32, u32)>
+        where
+            F: CellPredicate,
+    {
+        scan.cells(scan.order_or(Order::RowMajor), self.used_end())
+            .filter(|&cell| self.range.get_value(cell).is_some_and(|value| func.matches(value)))
+            .collect()
+    }
+
+    // string cells within `max_distance` edits of `needle`, closest first
+    pub fn find_fuzzy(&self, scan: &Scan, needle: &str, max_distance: usize) -> Vec<FuzzyMatch> {
+        let mut matches: Vec<FuzzyMatch> = scan.cells(scan.order_or(Order::RowMajor), self.used_end())
+            .filter_map(|cell| match self.range.get_value(cell) {
+                Some(Data::String(text)) => fuzzy_match(text, needle, max_distance).map(|(distance, score)| {
+                    FuzzyMatch { address: Address::from(cell), text: text.clone(), distance, score }
+                }),
+                _ => None,
+            })
+            .collect();
+        rank(&mut matches);
+        matches
+    }
+
+    // one T per row below `header_row`, fields matched to columns by header text
+    pub fn deserialize<T>(&self, header_row: u32) -> Result<Vec<T>, ExcelError>
+        where
+            T: DeserializeOwned,
+    {
+        deserialize_rows(&self.range, header_row)
+    }
+
+    // lazy version of iterate_row_values, yielding one vector per column with
+    // one entry per row; cells outside the used range come back as Empty.
+    // With scan.row_major() it yields one vector per row instead.
+    // Only the outer dimension is cut to the used range, the inner one gives the
+    // length of every vector (open ends still stop at the used range).
+    pub fn iter_row_values<'a, F>(&'a self, scan: &'a Scan, func: F) -> RowValues<'a, F>
+        where
+            F: RowPredicate,
+    {
+        let used_end = self.used_end();
+        let order = scan.order_or(Order::ColumnMajor);
+        let (outer, inner, outer_used, inner_used, outer_max, inner_max) = match order {
+            Order::ColumnMajor => (&scan.cols, &scan.rows,
+                used_end.map(|end| end.map(|(_, col)| col)), used_end.map(|end| end.map(|(row, _)| row)),
+                MAX_COLS, MAX_ROWS),
+            Order::RowMajor => (&scan.rows, &scan.cols,
+                used_end.map(|end| end.map(|(row, _)| row)), used_end.map(|end| end.map(|(_, col)| col)),
+                MAX_ROWS, MAX_COLS),
+        };
+        let (outer_end, outer_bound) = limits(outer_used, outer_max);
+        let (inner_end, _) = limits(inner_used, inner_max);
+        RowValues {
+            range: &self.range,
+            outer: outer.iter(outer_end, outer_bound),
+            inner,
+            inner_end,
+            order,
+            func,
+            compact: false,
+            done: false,
+        }
+    }
+}
+
+impl<'a, F> RowValues<'a, F> {
+    // leave out empty cells, inside the used range or not, instead of returning Empty
+    pub fn compact(mut self) -> Self {
+        self.compact = true;
+        self
+    }
+}
+
+impl<'a, F> Iterator for RowValues<'a, F>
     where
-        I: Iterator<Item=u32>,
-        J: Iterator<Item=u32>,
-        F: Fn(&DataType) -> bool,
+        F: RowPredicate,
 {
-    if let Some(Ok(ref range)) = r.worksheet_range(sheetname) {
-        for row in rows() {
-            for col in cols() {
-                if let Some(ref value) = range.get_value((row, col)) {
-                    if func(value) {
-                        return Some((row, col));
-                    }
-                }
+    type Item = Vec<Data>;
+
+    fn next(&mut self) -> Option<Vec<Data>> {
+        if self.done {
+            return None;
+        }
+        let outer = self.outer.next()?;
+        let mut vec = Vec::new();
+        for inner in self.inner.iter(self.inner_end, None) {
+            let cell = match self.order {
+                Order::ColumnMajor => (inner, outer),
+                Order::RowMajor => (outer, inner),
+            };
+            match self.range.get_value(cell) {
+                Some(Data::Empty) | None if self.compact => {}
+                Some(value) => vec.push(value.clone()), // value is &calamine::Data
+                None => vec.push(Data::Empty),
             }
         }
+        if self.func.matches_row(&vec) {
+            self.done = true;
+            return None;
+        }
+        Some(vec)
+    }
+}
+
+// The formulas of a sheet, as written in the cells but without the leading '='
+pub struct Formulas {
+    range: Range<String>,
+}
+
+impl Formulas {
+    pub fn empty() -> Self {
+        Self { range: Range::empty() }
+    }
+
+    pub fn get(&self, at: Address) -> Option<&str> {
+        self.range.get_value(at.into()).map(String::as_str).filter(|f| !f.is_empty())
+    }
+
+    // every cell with a formula, row by row
+    pub fn iter(&self) -> impl Iterator<Item=(Address, &str)> {
+        let (row0, col0) = self.range.start().unwrap_or((0, 0));
+        self.range.used_cells()
+            .filter(|(_, _, f)| !f.is_empty())
+            .map(move |(row, col, f)| (Address::new(row0 + row as u32, col0 + col as u32), f.as_str()))
+    }
+
+    // cells whose formula text matches `func`, e.g. Predicate::contains("VLOOKUP"), in scan order.
+    // The formula is passed as a string value.
+    pub fn find_all_cells<F>(&self, scan: &Scan, func: F) -> Vec<(u32, u32)>
+        where
+            F: CellPredicate,
+    {
+        let end = self.range.end();
+        scan.cells(scan.order_or(Order::RowMajor), Some(end))
+            .filter(|&cell| self.get(cell.into()).is_some_and(|f| func.matches(&Data::String(f.to_string()))))
+            .collect()
+    }
+}
+
+pub fn formulas_reader(r: &mut ExcelReader, sheetname: &str) -> Result<Formulas, ExcelError> {
+    if !r.sheet_names().iter().any(|s| s == sheetname) {
+        return Err(ExcelError::SheetNotFound(sheetname.to_string()));
+    }
+    Ok(Formulas { range: r.worksheet_formula(sheetname)? })
+}
+
+pub fn worksheet_reader(r: &mut ExcelReader, cache: &SheetCache, sheetname: &str) -> Result<Worksheet, ExcelError> {
+    let range = match cache.get(sheetname) {
+        Some(range) => range,
+        None => cache.insert(sheetname, worksheet_range(r, sheetname)?),
     };
-    None
+    Ok(Worksheet { range, clamp: true })
 }
 
-pub fn iterate_row_values_reader<I, J, F>(r: &mut XlsxReader,
-        sheetname: &str,
-        rows: &impl Fn() -> I,
-        cols: &impl Fn() -> J,
-        func: F
-    )
-    -> Vec<Vec<DataType>>
-    where
-        I: Iterator<Item=u32>,
-        J: Iterator<Item=u32>,
-        F: Fn(&Vec<DataType>) -> bool,
+// worksheet_reader for a worker thread, which opens a reader of its own only when
+// the sheet isn't cached yet, as calamine needs exclusive access to parse
+pub fn worksheet_reader_shared(r: &mut Option<ExcelReader>, path: &str, cache: &SheetCache, sheetname: &str)
+    -> Result<Worksheet, ExcelError>
 {
-    let mut values = Vec::new();
-    if let Some(Ok(ref range)) = r.worksheet_range(sheetname) {
-        for col in cols() {
-            let mut vec = Vec::new();
-            for row in rows() {
-                if let Some(value) = range.get_value((row, col)) {
-                    vec.push(value.clone()); // value is &calamine::DataType
-                }
-            }
-            if func(&vec) { break; };
-            values.push(vec);
-        }
+    if let Some(range) = cache.get(sheetname) {
+        return Ok(Worksheet { range, clamp: true });
+    }
+    let r = match r {
+        Some(r) => r,
+        None => r.insert(open_reader(path)?),
     };
-    values
-}
\ No newline at end of file
+    worksheet_reader(r, cache, sheetname)
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // B2:C4 with "a" in B2, true in C3 and 1 in B4, the other cells of the range empty
+    fn worksheet() -> Worksheet {
+        let mut range = Range::new((1, 1), (3, 2));
+        range.set_value((1, 1), Data::from("a"));
+        range.set_value((2, 2), Data::Bool(true));
+        range.set_value((3, 1), Data::Float(1.0));
+        Worksheet { range: Arc::new(range), clamp: true }
+    }
+
+    fn never(_: &Vec<Data>) -> bool {
+        false
+    }
+
+    #[test]
+    fn row_values_keep_empty_cells_in_place_unless_compact() {
+        let sheet = worksheet();
+        let scan = Scan::new(0..5, 1..);
+        let columns: Vec<_> = sheet.iter_row_values(&scan, never).collect();
+        assert_eq!(columns, vec![
+            vec![Data::Empty, Data::from("a"), Data::Empty, Data::Float(1.0), Data::Empty],
+            vec![Data::Empty, Data::Empty, Data::Bool(true), Data::Empty, Data::Empty],
+        ]);
+        let compact: Vec<_> = sheet.iter_row_values(&scan, never).compact().collect();
+        assert_eq!(compact, vec![vec![Data::from("a"), Data::Float(1.0)], vec![Data::Bool(true)]]);
+
+        let scan = Scan::new(1.., 0..3).row_major();
+        let rows: Vec<_> = sheet.iter_row_values(&scan, never).compact().collect();
+        assert_eq!(rows, vec![vec![Data::from("a")], vec![Data::Bool(true)], vec![Data::Float(1.0)]]);
+    }
+
+    #[test]
+    fn row_values_stop_at_the_first_matching_row() {
+        let sheet = worksheet();
+        let scan = Scan::new(1.., 0..3).row_major();
+        let stop = |row: &Vec<Data>| row.contains(&Data::Bool(true));
+        let rows: Vec<_> = sheet.iter_row_values(&scan, stop).collect();
+        assert_eq!(rows, vec![vec![Data::Empty, Data::from("a"), Data::Empty]]);
+    }
+
+    #[test]
+    fn open_ends_stop_at_the_used_range_unless_unclamped() {
+        let sheet = worksheet();
+        let any = |_: &Data| true;
+        assert_eq!(sheet.find_all_cells(&Scan::new(3.., 0..), any), vec![(3, 1), (3, 2)]);
+        assert_eq!(sheet.iter_row_values(&Scan::new(0..2, 0..), never).count(), 3);
+        // closed ranges of the inner dimension keep their length, outer ones are cut
+        assert_eq!(sheet.iter_row_values(&Scan::new(0..2, 0..10), never).count(), 3);
+
+        let sheet = worksheet().clamp_to_used_range(false);
+        assert_eq!(sheet.iter_row_values(&Scan::new(0..2, 0..), never).count(), MAX_COLS as usize);
+        assert_eq!(sheet.iter_row_values(&Scan::new(0..2, 0..10), never).count(), 10);
+        // cells past the used range are visited but have no value to match
+        assert_eq!(sheet.find_all_cells(&Scan::new(3..=3, 0..), any), vec![(3, 1), (3, 2)]);
+
+        let empty = Worksheet::empty();
+        assert_eq!(empty.find_all_cells(&Scan::all(), any), vec![]);
+        assert_eq!(empty.iter_row_values(&Scan::all(), never).count(), 0);
+    }
+}
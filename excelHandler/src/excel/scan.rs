@@ -0,0 +1,279 @@
+use std::convert::TryFrom;
+use std::ops::{Range, RangeFrom, RangeInclusive};
+
+use super::address::{CellRange, MAX_COLS, MAX_ROWS};
+
+
+// The row or column indices of a Scan
+#[derive(Debug, Clone, PartialEq)]
+pub enum Indices {
+    // start..=end every `step`, open-ended when end is None
+    Range { start: u32, end: Option<u32>, step: u32, rev: bool },
+    List(Vec<u32>),
+}
+
+impl Indices {
+    // explicit indices in the given order, e.g. (0..10).step_by(3) or [9, 1, 5]
+    pub fn list(indices: impl IntoIterator<Item=u32>) -> Self {
+        Indices::List(indices.into_iter().collect())
+    }
+
+    // every n-th index; a step of 0 counts as 1, and steps past u32::MAX stop at the first index
+    pub fn step(self, n: usize) -> Self {
+        let n = n.max(1);
+        match self {
+            Indices::Range { start, end, step, rev } => {
+                let step = step.saturating_mul(u32::try_from(n).unwrap_or(u32::MAX));
+                Indices::Range { start, end, step, rev }
+            }
+            Indices::List(list) => Indices::List(list.into_iter().step_by(n).collect()),
+        }
+    }
+
+    pub fn rev(self) -> Self {
+        match self {
+            Indices::Range { start, end, step, rev } => Indices::Range { start, end, step, rev: !rev },
+            Indices::List(mut list) => {
+                list.reverse();
+                Indices::List(list)
+            }
+        }
+    }
+
+    // The indices to visit. Open ends stop at `open_end`; with `bound` set, ranges are cut there too.
+    // Lists are always visited as given.
+    pub(crate) fn iter(&self, open_end: Option<u32>, bound: Option<Option<u32>>) -> Box<dyn Iterator<Item=u32> + '_> {
+        match *self {
+            Indices::Range { start, end, step, rev } => {
+                let end = match (end, bound) {
+                    (Some(end), None) => Some(end),
+                    (Some(end), Some(bound)) => bound.map(|b| b.min(end)),
+                    (None, _) => open_end,
+                };
+                let end = match end {
+                    Some(end) if end >= start => end,
+                    _ => return Box::new(std::iter::empty()),
+                };
+                let step = step as usize;
+                if rev {
+                    let last = start + (end - start) / step as u32 * step as u32; // same indices as forwards
+                    Box::new((start..=last).rev().step_by(step))
+                } else {
+                    Box::new((start..=end).step_by(step))
+                }
+            }
+            Indices::List(ref list) => Box::new(list.iter().cloned()),
+        }
+    }
+}
+
+impl From<Range<u32>> for Indices {
+    fn from(r: Range<u32>) -> Self {
+        match r.end.checked_sub(1) {
+            Some(end) => Indices::Range { start: r.start, end: Some(end), step: 1, rev: false },
+            None => Indices::List(Vec::new()),
+        }
+    }
+}
+
+impl From<RangeInclusive<u32>> for Indices {
+    fn from(r: RangeInclusive<u32>) -> Self {
+        Indices::Range { start: *r.start(), end: Some(*r.end()), step: 1, rev: false }
+    }
+}
+
+impl From<RangeFrom<u32>> for Indices {
+    fn from(r: RangeFrom<u32>) -> Self {
+        Indices::Range { start: r.start, end: None, step: 1, rev: false }
+    }
+}
+
+impl From<Vec<u32>> for Indices {
+    fn from(list: Vec<u32>) -> Self {
+        Indices::List(list)
+    }
+}
+
+impl From<&[u32]> for Indices {
+    fn from(list: &[u32]) -> Self {
+        Indices::List(list.to_vec())
+    }
+}
+
+impl<const N: usize> From<[u32; N]> for Indices {
+    fn from(list: [u32; N]) -> Self {
+        Indices::List(list.to_vec())
+    }
+}
+
+impl From<u32> for Indices {
+    fn from(i: u32) -> Self {
+        Indices::List(vec![i])
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Order {
+    RowMajor, // every col of a row before the next row
+    ColumnMajor,
+}
+
+// Which cells to visit and in which order, reusable across calls.
+//   Scan::new(0..10, 1..10)
+//   Scan::new(3..10, [1, 4]).rev_rows()
+//   Scan::from(&"B2:D20".parse::<CellRange>()?).step_rows(2)
+// Open-ended ranges like `5..` stop at the end of the sheet's used range.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scan {
+    pub rows: Indices,
+    pub cols: Indices,
+    order: Option<Order>, // None: the default of each method
+}
+
+impl Scan {
+    pub fn new(rows: impl Into<Indices>, cols: impl Into<Indices>) -> Self {
+        Self { rows: rows.into(), cols: cols.into(), order: None }
+    }
+
+    // every cell of the sheet
+    pub fn all() -> Self {
+        Self::new(0.., 0..)
+    }
+
+    pub fn step_rows(mut self, n: usize) -> Self {
+        self.rows = self.rows.step(n);
+        self
+    }
+
+    pub fn step_cols(mut self, n: usize) -> Self {
+        self.cols = self.cols.step(n);
+        self
+    }
+
+    pub fn rev_rows(mut self) -> Self {
+        self.rows = self.rows.rev();
+        self
+    }
+
+    pub fn rev_cols(mut self) -> Self {
+        self.cols = self.cols.rev();
+        self
+    }
+
+    // the find methods default to row-major order
+    pub fn row_major(mut self) -> Self {
+        self.order = Some(Order::RowMajor);
+        self
+    }
+
+    // iterate_row_values defaults to column-major order, one vector per column
+    pub fn column_major(mut self) -> Self {
+        self.order = Some(Order::ColumnMajor);
+        self
+    }
+
+    pub(crate) fn order_or(&self, default: Order) -> Order {
+        self.order.unwrap_or(default)
+    }
+
+    // (row, col) pairs in scan order, see limits() for `used_end`
+    pub(crate) fn cells<'s>(&'s self, order: Order, used_end: Option<Option<(u32, u32)>>)
+        -> Box<dyn Iterator<Item=(u32, u32)> + 's>
+    {
+        let (row_end, row_bound) = limits(used_end.map(|end| end.map(|(row, _)| row)), MAX_ROWS);
+        let (col_end, col_bound) = limits(used_end.map(|end| end.map(|(_, col)| col)), MAX_COLS);
+        match order {
+            Order::RowMajor => Box::new(self.rows.iter(row_end, row_bound).flat_map(move |row| {
+                self.cols.iter(col_end, col_bound).map(move |col| (row, col))
+            })),
+            Order::ColumnMajor => Box::new(self.cols.iter(col_end, col_bound).flat_map(move |col| {
+                self.rows.iter(row_end, row_bound).map(move |row| (row, col))
+            })),
+        }
+    }
+}
+
+// Open end and bound of one dimension for Indices::iter. `used_end` is the last used index
+// when clamping to the used range (None for a sheet without cells), or None to go up to the sheet limit.
+pub(crate) fn limits(used_end: Option<Option<u32>>, max: u32) -> (Option<u32>, Option<Option<u32>>) {
+    match used_end {
+        Some(end) => (end, Some(end)),
+        None => (Some(max - 1), None),
+    }
+}
+
+impl From<&CellRange> for Scan {
+    fn from(range: &CellRange) -> Self {
+        Self::new(range.rows(), range.cols())
+    }
+}
+
+impl From<CellRange> for Scan {
+    fn from(range: CellRange) -> Self {
+        Self::from(&range)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn indices(indices: &Indices) -> Vec<u32> {
+        indices.iter(None, None).collect()
+    }
+
+    #[test]
+    fn indices_step_and_reverse() {
+        assert_eq!(indices(&Indices::from(0..10).step(3)), vec![0, 3, 6, 9]);
+        // reversed steps visit the same indices as forwards
+        assert_eq!(indices(&Indices::from(1..=10).step(4).rev()), vec![9, 5, 1]);
+        assert_eq!(indices(&Indices::from([9, 1, 5, 7]).step(2)), vec![9, 5]);
+        assert_eq!(indices(&Indices::from(vec![9, 1, 5]).rev()), vec![5, 1, 9]);
+        assert_eq!(indices(&Indices::from(5..5)), Vec::<u32>::new());
+    }
+
+    #[test]
+    fn open_ends_and_bounds() {
+        let open = Indices::from(2..);
+        assert_eq!(open.iter(Some(4), None).collect::<Vec<_>>(), vec![2, 3, 4]);
+        assert_eq!(open.iter(None, None).count(), 0);
+        let closed = Indices::from(0..10);
+        assert_eq!(closed.iter(Some(4), Some(Some(2))).collect::<Vec<_>>(), vec![0, 1, 2]);
+        assert_eq!(closed.iter(Some(4), Some(None)).count(), 0); // a sheet without cells
+        assert_eq!(Indices::from([20, 1]).iter(Some(4), Some(Some(2))).collect::<Vec<_>>(), vec![20, 1]);
+    }
+
+    #[test]
+    fn cells_follow_the_order() {
+        let scan = Scan::new(0..2, [3, 1]);
+        let row_major: Vec<_> = scan.cells(Order::RowMajor, None).collect();
+        assert_eq!(row_major, vec![(0, 3), (0, 1), (1, 3), (1, 1)]);
+        let column_major: Vec<_> = scan.cells(Order::ColumnMajor, None).collect();
+        assert_eq!(column_major, vec![(0, 3), (1, 3), (0, 1), (1, 1)]);
+        // open ends stop at the used range, and closed ranges are cut there
+        let scan = Scan::new(1.., 0..5).rev_rows();
+        let cells: Vec<_> = scan.cells(Order::RowMajor, Some(Some((2, 1)))).collect();
+        assert_eq!(cells, vec![(2, 0), (2, 1), (1, 0), (1, 1)]);
+        assert_eq!(scan.order_or(Order::ColumnMajor), Order::ColumnMajor);
+        assert_eq!(scan.row_major().order_or(Order::ColumnMajor), Order::RowMajor);
+    }
+
+    #[test]
+    fn ranges_scan_their_cells() {
+        let range: CellRange = "B2:C3".parse().unwrap();
+        let cells: Vec<_> = Scan::from(&range).cells(Order::RowMajor, None).collect();
+        assert_eq!(cells, vec![(1, 1), (1, 2), (2, 1), (2, 2)]);
+    }
+
+    #[test]
+    fn steps_of_zero_or_past_u32_do_not_panic() {
+        assert_eq!(indices(&Indices::from(0..5).step(0)), vec![0, 1, 2, 3, 4]);
+        assert_eq!(indices(&Indices::from(vec![7, 3, 5]).step(0)), vec![7, 3, 5]);
+        let huge = Indices::from(2..=10).step(4).step(usize::MAX);
+        assert_eq!(huge, Indices::Range { start: 2, end: Some(10), step: u32::MAX, rev: false });
+        assert_eq!(indices(&huge), vec![2]);
+        assert_eq!(indices(&huge.rev()), vec![2]);
+    }
+}
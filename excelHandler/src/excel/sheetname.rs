@@ -0,0 +1,91 @@
+use std::error;
+use std::fmt;
+
+const MAX_LEN: usize = 31;
+const FORBIDDEN: &[char] = &['[', ']', ':', '*', '?', '/', '\\'];
+
+
+// A sheet name Excel would refuse
+#[derive(Debug, Clone, PartialEq)]
+pub enum SheetNameError {
+    Empty,
+    TooLong(usize),
+    InvalidCharacter(char),
+    Apostrophe, // at the start or the end
+    Reserved,
+    Duplicate(String),
+}
+
+impl fmt::Display for SheetNameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SheetNameError::Empty => write!(f, "Sheet name is empty"),
+            SheetNameError::TooLong(n) => write!(f, "Sheet name has {} characters, at most {} are allowed", n, MAX_LEN),
+            SheetNameError::InvalidCharacter(c) => write!(f, "Sheet name contains the forbidden character '{}'", c),
+            SheetNameError::Apostrophe => write!(f, "Sheet name starts or ends with an apostrophe"),
+            SheetNameError::Reserved => write!(f, "\"History\" is reserved by Excel"),
+            SheetNameError::Duplicate(name) => write!(f, "Sheet \"{}\" already exists", name),
+        }
+    }
+}
+
+impl error::Error for SheetNameError {}
+
+// check `name` against Excel's rules; the comparison with `existing` names ignores case
+pub fn validate_sheet_name<'a>(name: &str, existing: impl IntoIterator<Item=&'a String>) -> Result<(), SheetNameError> {
+    let len = name.chars().count();
+    if len == 0 {
+        return Err(SheetNameError::Empty);
+    }
+    if len > MAX_LEN {
+        return Err(SheetNameError::TooLong(len));
+    }
+    if let Some(c) = name.chars().find(|c| FORBIDDEN.contains(c)) {
+        return Err(SheetNameError::InvalidCharacter(c));
+    }
+    if name.starts_with('\'') || name.ends_with('\'') {
+        return Err(SheetNameError::Apostrophe);
+    }
+    if name.eq_ignore_ascii_case("History") {
+        return Err(SheetNameError::Reserved);
+    }
+    let lower = name.to_lowercase();
+    if let Some(other) = existing.into_iter().find(|s| s.to_lowercase() == lower) {
+        return Err(SheetNameError::Duplicate(other.clone()));
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validate(name: &str) -> Result<(), SheetNameError> {
+        let existing = ["Sheet1".to_string(), "Données".to_string()];
+        validate_sheet_name(name, &existing)
+    }
+
+    #[test]
+    fn names_follow_excel_rules() {
+        assert_eq!(validate("Q1 report (draft)"), Ok(()));
+        assert_eq!(validate(""), Err(SheetNameError::Empty));
+        // the limit counts characters, not bytes
+        assert_eq!(validate(&"あ".repeat(31)), Ok(()));
+        assert_eq!(validate(&"a".repeat(32)), Err(SheetNameError::TooLong(32)));
+        for c in FORBIDDEN {
+            assert_eq!(validate(&format!("a{}b", c)), Err(SheetNameError::InvalidCharacter(*c)));
+        }
+        assert_eq!(validate("'quoted"), Err(SheetNameError::Apostrophe));
+        assert_eq!(validate("quoted'"), Err(SheetNameError::Apostrophe));
+        assert_eq!(validate("it's"), Ok(()));
+        assert_eq!(validate("history"), Err(SheetNameError::Reserved));
+    }
+
+    #[test]
+    fn duplicates_are_found_ignoring_case() {
+        assert_eq!(validate("SHEET1"), Err(SheetNameError::Duplicate("Sheet1".to_string())));
+        assert_eq!(validate("DONNÉES"), Err(SheetNameError::Duplicate("Données".to_string())));
+        assert_eq!(validate("Sheet2"), Ok(()));
+    }
+}
@@ -0,0 +1,236 @@
+use calamine::{Data, Reader, Sheets};
+
+use super::error::ExcelError;
+use super::predicate::CellPredicate;
+use super::reader::{self, ExcelReader};
+use super::scan::{Indices, Order, Scan};
+
+
+// A sheet read row by row with its own reader, see ExcelHandle::stream_rows.
+// .xlsx and .xlsb sheets are parsed incrementally; .xls and .ods ones are parsed
+// whole first, as calamine can't stream them.
+pub struct RowStream {
+    reader: Option<ExcelReader>, // None for a created workbook that isn't saved yet
+    sheetname: String,
+}
+
+// Iterator returned by RowStream::rows, yielding (row, values) for every row with cells.
+// `values` runs from column 0 to the last cell of the row, with Empty for gaps.
+pub struct Rows<'a> {
+    next_cell: NextCell<'a>,
+    pending: Option<((u32, u32), Data)>, // first cell of the next row
+    done: bool,
+}
+
+type NextCell<'a> = Box<dyn FnMut() -> Result<Option<((u32, u32), Data)>, ExcelError> + 'a>;
+
+pub fn stream_reader(path: &str, sheetname: &str) -> Result<RowStream, ExcelError> {
+    let reader = reader::open_reader(path)?;
+    if !reader.sheet_names().iter().any(|s| s == sheetname) {
+        return Err(ExcelError::SheetNotFound(sheetname.to_string()));
+    }
+    Ok(RowStream { reader: Some(reader), sheetname: sheetname.to_string() })
+}
+
+pub fn empty_stream(sheetname: &str) -> RowStream {
+    RowStream { reader: None, sheetname: sheetname.to_string() }
+}
+
+impl RowStream {
+    pub fn rows(&mut self) -> Result<Rows<'_>, ExcelError> {
+        let next_cell: NextCell<'_> = match self.reader {
+            Some(Sheets::Xlsx(ref mut xlsx)) => {
+                let mut cells = xlsx.worksheet_cells_reader(&self.sheetname)?;
+                Box::new(move || Ok(cells.next_cell()?.map(|c| (c.get_position(), c.get_value().clone().into()))))
+            }
+            Some(Sheets::Xlsb(ref mut xlsb)) => {
+                let mut cells = xlsb.worksheet_cells_reader(&self.sheetname).map_err(calamine::Error::Xlsb)?;
+                Box::new(move || {
+                    let cell = cells.next_cell().map_err(calamine::Error::Xlsb)?;
+                    Ok(cell.map(|c| (c.get_position(), c.get_value().clone().into())))
+                })
+            }
+            Some(ref mut r) => {
+                let range = r.worksheet_range(&self.sheetname)?;
+                let mut cells = range.used_cells().map(|(row, col, value)| ((row as u32, col as u32), value.clone()))
+                    .collect::<Vec<_>>()
+                    .into_iter();
+                let start = range.start().unwrap_or((0, 0));
+                Box::new(move || Ok(cells.next().map(|((row, col), value)| ((start.0 + row, start.1 + col), value))))
+            }
+            None => Box::new(|| Ok(None)),
+        };
+        Ok(Rows { next_cell, pending: None, done: false })
+    }
+
+    // the first cell matching `func`, reading only up to its row. Like find_cell the scan
+    // is row-major, but its rows must ascend since the file can't be read backwards.
+    pub fn find_cell<F>(&mut self, scan: &Scan, func: F) -> Result<Option<(u32, u32)>, ExcelError>
+        where
+            F: CellPredicate,
+    {
+        if !is_streamable(scan) {
+            return Err(ExcelError::InvalidScan("streaming needs ascending rows in row-major order"));
+        }
+        if scan.rows == Indices::List(Vec::new()) {
+            return Ok(None);
+        }
+        let last_row = last(&scan.rows);
+        for row in self.rows()? {
+            let (row, values) = row?;
+            if last_row.is_some_and(|last| row > last) {
+                break;
+            }
+            if !contains(&scan.rows, row) {
+                continue;
+            }
+            let end = values.len() as u32 - 1;
+            for col in scan.cols.iter(Some(end), Some(Some(end))) {
+                if values.get(col as usize).is_some_and(|value| func.matches(value)) {
+                    return Ok(Some((row, col)));
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl<'a> Iterator for Rows<'a> {
+    type Item = Result<(u32, Vec<Data>), ExcelError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let mut values = Vec::new();
+        let mut row = None;
+        loop {
+            let cell = match self.pending.take() {
+                Some(cell) => Some(cell),
+                None => match (self.next_cell)() {
+                    Ok(cell) => cell,
+                    Err(e) => {
+                        self.done = true;
+                        return Some(Err(e));
+                    }
+                },
+            };
+            let ((r, c), value) = match cell {
+                Some(cell) => cell,
+                None => {
+                    self.done = true;
+                    return row.map(|r| Ok((r, values)));
+                }
+            };
+            if row.is_some_and(|row| row != r) {
+                self.pending = Some(((r, c), value));
+                return row.map(|r| Ok((r, values)));
+            }
+            row = Some(r);
+            if values.len() <= c as usize {
+                values.resize(c as usize + 1, Data::Empty);
+            }
+            values[c as usize] = value;
+        }
+    }
+}
+
+// rows visited in file order, i.e. ascending and row by row
+pub fn is_streamable(scan: &Scan) -> bool {
+    let ascending = match scan.rows {
+        Indices::Range { rev, .. } => !rev,
+        Indices::List(ref list) => list.windows(2).all(|w| w[0] < w[1]),
+    };
+    ascending && scan.order_or(Order::RowMajor) == Order::RowMajor
+}
+
+fn contains(indices: &Indices, i: u32) -> bool {
+    match *indices {
+        Indices::Range { start, end, step, .. } => {
+            i >= start && end.is_none_or(|end| i <= end) && (i - start).is_multiple_of(step)
+        }
+        Indices::List(ref list) => list.contains(&i),
+    }
+}
+
+// None for open-ended ranges
+fn last(indices: &Indices) -> Option<u32> {
+    match *indices {
+        Indices::Range { end, .. } => end,
+        Indices::List(ref list) => list.iter().max().cloned(),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::creater::{save_creater, set_range_values_creater, Book};
+    use super::super::input::CellInput;
+    use super::super::predicate::Predicate;
+
+    // Rows over cells given in file order
+    fn rows(cells: Vec<((u32, u32), Data)>) -> Rows<'static> {
+        let mut cells = cells.into_iter();
+        Rows { next_cell: Box::new(move || Ok(cells.next())), pending: None, done: false }
+    }
+
+    #[test]
+    fn rows_come_in_file_order_with_gaps_filled() {
+        let cells = vec![
+            ((1, 1), Data::from("a")),
+            ((4, 0), Data::Int(1)),
+            ((4, 3), Data::Int(2)),
+            ((7, 2), Data::Bool(true)),
+        ];
+        let found: Vec<_> = rows(cells).collect::<Result<_, _>>().unwrap();
+        assert_eq!(found, vec![
+            (1, vec![Data::Empty, Data::from("a")]),
+            (4, vec![Data::Int(1), Data::Empty, Data::Empty, Data::Int(2)]),
+            (7, vec![Data::Empty, Data::Empty, Data::Bool(true)]),
+        ]);
+        assert_eq!(rows(Vec::new()).count(), 0);
+    }
+
+    #[test]
+    fn rows_stop_after_an_error() {
+        let mut calls = 0;
+        let mut rows = Rows {
+            next_cell: Box::new(move || {
+                calls += 1;
+                match calls {
+                    1 => Ok(Some(((0, 0), Data::Int(1)))),
+                    _ => Err(ExcelError::InvalidPackage("broken sheet")),
+                }
+            }),
+            pending: None,
+            done: false,
+        };
+        assert!(matches!(rows.next(), Some(Err(ExcelError::InvalidPackage(_)))));
+        assert!(rows.next().is_none());
+    }
+
+    #[test]
+    fn find_cell_reads_sparse_rows_of_a_file() {
+        let path = std::env::temp_dir().join(format!("excelhandler-{}-stream.xlsx", std::process::id()));
+        let path = path.to_string_lossy().into_owned();
+        let mut book = Book::default();
+        for (row, col, text) in [(2, 3, "x"), (5, 1, "x"), (9, 0, "y")] {
+            let data = [vec![CellInput::from(Data::from(text))]];
+            set_range_values_creater(&mut book, "Sheet1", row, col, &data, None).unwrap();
+        }
+        save_creater(&mut book, &path).unwrap();
+
+        let mut stream = stream_reader(&path, "Sheet1").unwrap();
+        let rows: Vec<u32> = stream.rows().unwrap().map(|row| row.unwrap().0).collect();
+        assert_eq!(rows, vec![2, 5, 9]);
+        let x = Predicate::eq("x");
+        assert_eq!(stream.find_cell(&Scan::new(0.., 0..), &x).unwrap(), Some((2, 3)));
+        assert_eq!(stream.find_cell(&Scan::new(3.., 0..), &x).unwrap(), Some((5, 1)));
+        assert_eq!(stream.find_cell(&Scan::new([2, 9], 0..3), &x).unwrap(), None);
+        assert_eq!(stream.find_cell(&Scan::new(0..=2, 0..), Predicate::eq("y")).unwrap(), None);
+        let backwards = Scan::new(0.., 0..).rev_rows();
+        assert!(matches!(stream.find_cell(&backwards, &x), Err(ExcelError::InvalidScan(_))));
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,197 @@
+use libxlsxwriter_sys as sys;
+
+use super::lxw::Format;
+
+
+// The look of cells in a created workbook, built up from CellStyle::new():
+//   CellStyle::new().bold().fill(0xDDEBF7).border(Border::Thin).num_format("#,##0.00")
+// Colours are 0xRRGGBB. Unset fields keep Excel's defaults (Calibri 11, no fill, no border).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CellStyle {
+    pub font_name: Option<String>,
+    pub font_size: Option<f64>,
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub font_color: Option<u32>,
+    pub fill: Option<u32>, // solid background
+    pub border: Option<Border>, // on all four sides
+    pub border_color: Option<u32>,
+    pub align: Option<Align>,
+    pub valign: Option<VAlign>,
+    pub wrap: bool,
+    pub num_format: Option<String>, // e.g. "0.00%" or "yyyy-mm-dd"
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Border {
+    Thin,
+    Medium,
+    Thick,
+    Dashed,
+    Dotted,
+    Double,
+    Hair,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Align {
+    Left,
+    Center,
+    Right,
+    Fill,
+    Justify,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VAlign {
+    Top,
+    Center,
+    Bottom,
+}
+
+impl CellStyle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn font(mut self, name: &str, size: f64) -> Self {
+        self.font_name = Some(name.to_string());
+        self.font_size = Some(size);
+        self
+    }
+
+    pub fn bold(mut self) -> Self {
+        self.bold = true;
+        self
+    }
+
+    pub fn italic(mut self) -> Self {
+        self.italic = true;
+        self
+    }
+
+    pub fn underline(mut self) -> Self {
+        self.underline = true;
+        self
+    }
+
+    pub fn font_color(mut self, rgb: u32) -> Self {
+        self.font_color = Some(rgb);
+        self
+    }
+
+    pub fn fill(mut self, rgb: u32) -> Self {
+        self.fill = Some(rgb);
+        self
+    }
+
+    pub fn border(mut self, border: Border) -> Self {
+        self.border = Some(border);
+        self
+    }
+
+    pub fn border_color(mut self, rgb: u32) -> Self {
+        self.border_color = Some(rgb);
+        self
+    }
+
+    pub fn align(mut self, align: Align) -> Self {
+        self.align = Some(align);
+        self
+    }
+
+    pub fn valign(mut self, valign: VAlign) -> Self {
+        self.valign = Some(valign);
+        self
+    }
+
+    pub fn wrap(mut self) -> Self {
+        self.wrap = true;
+        self
+    }
+
+    pub fn num_format(mut self, format: &str) -> Self {
+        self.num_format = Some(format.to_string());
+        self
+    }
+
+    // set up a libxlsxwriter format to look like this style
+    pub fn apply(&self, format: &mut Format) {
+        if let Some(ref name) = self.font_name {
+            format.set_font_name(name);
+        }
+        if let Some(size) = self.font_size {
+            format.set_font_size(size);
+        }
+        if self.bold {
+            format.set_bold();
+        }
+        if self.italic {
+            format.set_italic();
+        }
+        if self.underline {
+            format.set_underline();
+        }
+        if let Some(rgb) = self.font_color {
+            format.set_font_color(rgb);
+        }
+        if let Some(rgb) = self.fill {
+            format.set_fill(rgb);
+        }
+        if let Some(border) = self.border {
+            format.set_border(border.format());
+        }
+        if let Some(rgb) = self.border_color {
+            format.set_border_color(rgb);
+        }
+        if let Some(align) = self.align {
+            format.set_align(align.format());
+        }
+        if let Some(valign) = self.valign {
+            format.set_align(valign.format());
+        }
+        if self.wrap {
+            format.set_text_wrap();
+        }
+        if let Some(ref num_format) = self.num_format {
+            format.set_num_format(num_format);
+        }
+    }
+}
+
+impl Border {
+    fn format(self) -> sys::lxw_format_borders {
+        match self {
+            Border::Thin => sys::lxw_format_borders_LXW_BORDER_THIN,
+            Border::Medium => sys::lxw_format_borders_LXW_BORDER_MEDIUM,
+            Border::Thick => sys::lxw_format_borders_LXW_BORDER_THICK,
+            Border::Dashed => sys::lxw_format_borders_LXW_BORDER_DASHED,
+            Border::Dotted => sys::lxw_format_borders_LXW_BORDER_DOTTED,
+            Border::Double => sys::lxw_format_borders_LXW_BORDER_DOUBLE,
+            Border::Hair => sys::lxw_format_borders_LXW_BORDER_HAIR,
+        }
+    }
+}
+
+impl Align {
+    fn format(self) -> sys::lxw_format_alignments {
+        match self {
+            Align::Left => sys::lxw_format_alignments_LXW_ALIGN_LEFT,
+            Align::Center => sys::lxw_format_alignments_LXW_ALIGN_CENTER,
+            Align::Right => sys::lxw_format_alignments_LXW_ALIGN_RIGHT,
+            Align::Fill => sys::lxw_format_alignments_LXW_ALIGN_FILL,
+            Align::Justify => sys::lxw_format_alignments_LXW_ALIGN_JUSTIFY,
+        }
+    }
+}
+
+impl VAlign {
+    fn format(self) -> sys::lxw_format_alignments {
+        match self {
+            VAlign::Top => sys::lxw_format_alignments_LXW_ALIGN_VERTICAL_TOP,
+            VAlign::Center => sys::lxw_format_alignments_LXW_ALIGN_VERTICAL_CENTER,
+            VAlign::Bottom => sys::lxw_format_alignments_LXW_ALIGN_VERTICAL_BOTTOM,
+        }
+    }
+}
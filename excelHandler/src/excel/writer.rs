@@ -0,0 +1,1139 @@
+use std::collections::{BTreeMap, HashMap};
+use std::collections::btree_map;
+use std::io::{self, Write};
+use std::iter::Peekable;
+
+use calamine::Data;
+use quick_xml::escape::{partial_escape, unescape};
+use quick_xml::events::{BytesStart, BytesText, Event};
+use quick_xml::{Reader, Writer};
+
+use super::address::{Address, CellRange, MAX_COLS, MAX_ROWS};
+use super::dates::DateKind;
+use super::error::ExcelError;
+use super::formula;
+use super::input::CellInput;
+use super::package::{Package, attribute, replace_attribute, without_attribute, xml_error};
+
+const SHARED_STRINGS: &str = "xl/sharedStrings.xml";
+const SHARED_STRINGS_TYPE: &str = "application/vnd.openxmlformats-officedocument.spreadsheetml.sharedStrings+xml";
+const WORKSHEET_TYPE: &str = "application/vnd.openxmlformats-officedocument.spreadsheetml.worksheet+xml";
+// elements holding formula text that may refer to a sheet by name
+const FORMULA_TAGS: [&[u8]; 7] = [
+    b"f", // cells and chart series
+    b"formula", // conditional formats
+    b"formula1", // data validation
+    b"formula2",
+    b"definedName",
+    b"calculatedColumnFormula", // tables
+    b"totalsRowFormula",
+];
+const EMPTY_SHEET: &[u8] = br#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<worksheet xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main" xmlns:r="http://schemas.openxmlformats.org/officeDocument/2006/relationships"><sheetData/></worksheet>"#;
+
+type Cells<'a> = BTreeMap<u32, BTreeMap<u32, &'a CellInput>>; // row -> col -> value
+
+// what the cells an array formula spans over are set to, besides its first one
+static CLEARED: CellInput = CellInput::Value(Data::Empty);
+type Rows<'a, 'b> = Peekable<btree_map::Iter<'b, u32, BTreeMap<u32, &'a CellInput>>>;
+type Cols<'a, 'b> = Peekable<btree_map::Iter<'b, u32, &'a CellInput>>;
+
+
+// Write a data matrix into the package, top-left cell at (row, col).
+// Only the sheet XML, the shared strings and, for dates, the cell formats are
+// rewritten; styles of overwritten cells are kept and every other part is copied as is.
+pub fn set_range_values_writer(path: &str,
+        sheetname: &str,
+        row: u32,
+        col: u32,
+        data: &[Vec<CellInput>]
+    )
+    -> Result<(), ExcelError>
+{
+    let mut package = Package::open(path)?;
+    let part = package.sheet_part(sheetname)?;
+    let xml = package.read(&part)?;
+    let mut cells: Cells = BTreeMap::new();
+    let mut arrays = Vec::new();
+    for (i, values) in data.iter().enumerate() {
+        for (j, value) in values.iter().enumerate() {
+            let (r, c) = (row as usize + i, col as usize + j);
+            if r >= MAX_ROWS as usize || c >= MAX_COLS as usize {
+                return Err(ExcelError::AddressOutOfRange { row: r, col: c });
+            }
+            value.check_finite(Address::new(r as u32, c as u32))?;
+            arrays.extend(value.array_range(r, c)?);
+            cells.entry(r as u32).or_default().insert(c as u32, value);
+        }
+    }
+    // values and formulas under an array formula would make the file invalid
+    for range in arrays {
+        for r in range.rows() {
+            for c in range.cols().filter(|&c| Address::new(r, c) != range.start) {
+                cells.entry(r).or_default().insert(c, &CLEARED);
+            }
+        }
+    }
+    // the sheet's array formulas can only be replaced from their first cell, which
+    // clears the rest of them
+    for range in array_formulas(&xml)? {
+        let written = |at: Address| cells.get(&at.row).is_some_and(|row| row.contains_key(&at.col));
+        if written(range.start) {
+            for r in range.rows() {
+                for c in range.cols() {
+                    cells.entry(r).or_default().entry(c).or_insert(&CLEARED);
+                }
+            }
+        } else if cells.range(range.rows()).any(|(_, row)| row.range(range.cols()).next().is_some()) {
+            return Err(ExcelError::ArrayFormulaPart(range.on_sheet(sheetname)));
+        }
+    }
+
+    let mut strings = SharedStrings::load(&mut package)?;
+    let mut formats = CellFormats::load(&mut package)?;
+    let mut sheet = SheetWriter::new(&mut strings, &mut formats);
+    let xml = sheet.update(&xml, &cells)?;
+    let (formulas_removed, formulas_added) = (sheet.formulas_removed, sheet.formulas_added);
+    package.write(&part, xml);
+    strings.store(&mut package)?;
+    formats.store(&mut package)?;
+
+    // the calculation chain would point at cells that lost their formula;
+    // Excel rebuilds it when it is missing
+    let calc_chain = package.workbook_part("calcChain")?;
+    if let Some(ref calc_chain) = calc_chain {
+        if formulas_removed {
+            package.remove_workbook_part(calc_chain)?;
+        }
+    }
+    // new formulas only carry the cached value they were given
+    if calc_chain.is_some() || formulas_added {
+        package.set_full_calc_on_load()?;
+    }
+    package.save(path)
+}
+
+
+// insert an empty worksheet at `index` in the tab order
+pub fn add_sheet_writer(path: &str, sheetname: &str, index: usize) -> Result<(), ExcelError> {
+    let mut package = Package::open(path)?;
+    let mut sheets = package.sheets()?;
+    let part = (1..).map(|n| format!("xl/worksheets/sheet{}.xml", n))
+        .find(|part| !package.contains(part)).unwrap_or_default();
+    package.write(&part, EMPTY_SHEET.to_vec());
+    let rid = package.add_workbook_part(&part, "worksheet", WORKSHEET_TYPE)?;
+    let mut sheet_id = 0;
+    for sheet in &sheets {
+        if let Some(id) = attribute(sheet, b"sheetId")?.and_then(|id| id.parse::<u32>().ok()) {
+            sheet_id = sheet_id.max(id);
+        }
+    }
+    let sheet_id = (sheet_id + 1).to_string();
+    let sheet = BytesStart::new("sheet").with_attributes(vec![
+        ("name", sheetname),
+        ("sheetId", sheet_id.as_str()),
+        ("r:id", rid.as_str()),
+    ]);
+    let moved: Vec<_> = (0..sheets.len()).map(|i| Some(if i < index { i } else { i + 1 })).collect();
+    sheets.insert(index, sheet);
+    package.set_sheets(&sheets, &moved)?;
+    package.save(path)
+}
+
+// rename a sheet together with the references to it in formulas, defined names,
+// data validation, conditional formats, charts, tables, hyperlinks and pivot caches
+pub fn rename_sheet_writer(path: &str, cur_name: &str, new_name: &str) -> Result<(), ExcelError> {
+    let mut package = Package::open(path)?;
+    let mut sheets = package.sheets()?;
+    let i = sheet_position(&sheets, cur_name)?;
+    sheets[i] = replace_attribute(&sheets[i], "name", new_name);
+    let moved: Vec<_> = (0..sheets.len()).map(Some).collect();
+    package.set_sheets(&sheets, &moved)?;
+    rename_references(&mut package, cur_name, Some(new_name))?;
+    package.save(path)
+}
+
+// point the references to sheet `old` in every part at `new`,
+// or turn those in formulas into #REF! when the sheet is deleted (`new` is None)
+fn rename_references(package: &mut Package, old: &str, new: Option<&str>) -> Result<(), ExcelError> {
+    for part in package.part_names() {
+        if !part.starts_with("xl/") || !part.ends_with(".xml") || part == SHARED_STRINGS {
+            continue;
+        }
+        let xml = package.read(&part)?;
+        if let Some(xml) = rename_references_in(&xml, old, new)? {
+            package.write(&part, xml);
+        }
+    }
+    Ok(())
+}
+
+// `xml` with its references to sheet `old` pointing at `new`, None when it has none.
+// Links and pivot table sources to a deleted sheet are left as they are.
+fn rename_references_in(xml: &[u8], old: &str, new: Option<&str>) -> Result<Option<Vec<u8>>, ExcelError> {
+    let mut reader = Reader::from_reader(xml);
+    let mut writer = Writer::new(Vec::with_capacity(xml.len()));
+    let mut changed = false;
+    let mut text: Option<String> = None; // of the formula element being read
+    loop {
+        let event = reader.read_event().map_err(xml_error)?;
+        if let Some(ref mut t) = text {
+            match event {
+                Event::Text(ref e) => t.push_str(&e.xml10_content().map_err(xml_error)?),
+                Event::CData(ref e) => t.push_str(&String::from_utf8_lossy(e)),
+                Event::GeneralRef(ref e) => {
+                    let name = e.xml10_content().map_err(xml_error)?;
+                    t.push_str(&unescape(&format!("&{};", name)).map_err(xml_error)?);
+                }
+                _ => {
+                    let formula = text.take().unwrap_or_default();
+                    let renamed = match new {
+                        Some(new) => formula::rename_sheet(&formula, old, new),
+                        None => formula::remove_sheet(&formula, old),
+                    };
+                    changed |= renamed.is_some();
+                    let formula = renamed.unwrap_or(formula);
+                    writer.write_event(Event::Text(BytesText::from_escaped(partial_escape(&formula))))?;
+                    writer.write_event(event)?;
+                }
+            }
+            continue;
+        }
+        match event {
+            Event::Start(ref e) if FORMULA_TAGS.contains(&e.local_name().as_ref()) => {
+                writer.write_event(event.borrow())?;
+                text = Some(String::new());
+            }
+            // links within the workbook, e.g. location="'My sheet'!A1"
+            Event::Start(ref e) | Event::Empty(ref e) if e.local_name().as_ref() == b"hyperlink" => {
+                let location = attribute(e, b"location")?;
+                match location.zip(new).and_then(|(l, new)| formula::rename_sheet(&l, old, new)) {
+                    Some(location) => {
+                        changed = true;
+                        let start = replace_attribute(e, "location", &location);
+                        match event {
+                            Event::Start(_) => writer.write_event(Event::Start(start))?,
+                            _ => writer.write_event(Event::Empty(start))?,
+                        }
+                    }
+                    None => writer.write_event(event.borrow())?,
+                }
+            }
+            // the source range of a pivot table
+            Event::Start(ref e) | Event::Empty(ref e) if e.local_name().as_ref() == b"worksheetSource" => {
+                let sheet = attribute(e, b"sheet")?;
+                if let Some(new) = new.filter(|_| sheet.is_some_and(|s| s.to_lowercase() == old.to_lowercase())) {
+                    changed = true;
+                    let start = replace_attribute(e, "sheet", new);
+                    match event {
+                        Event::Start(_) => writer.write_event(Event::Start(start))?,
+                        _ => writer.write_event(Event::Empty(start))?,
+                    }
+                } else {
+                    writer.write_event(event.borrow())?;
+                }
+            }
+            Event::Eof => break,
+            e => writer.write_event(e)?,
+        }
+    }
+    Ok(if changed { Some(writer.into_inner()) } else { None })
+}
+
+// delete a sheet together with the parts only it used (drawings, comments, tables, ...),
+// turning references to it elsewhere into #REF!
+pub fn delete_sheet_writer(path: &str, sheetname: &str) -> Result<(), ExcelError> {
+    let mut package = Package::open(path)?;
+    let mut sheets = package.sheets()?;
+    let i = sheet_position(&sheets, sheetname)?;
+    if sheets.len() == 1 {
+        return Err(ExcelError::LastSheet);
+    }
+    let part = package.sheet_part(sheetname)?;
+    let related = package.related_parts(&part)?;
+    package.remove_workbook_part(&part)?;
+    remove_unused_parts(&mut package, related)?;
+    let moved: Vec<_> = (0..sheets.len()).map(|j| match j {
+        j if j < i => Some(j),
+        j if j > i => Some(j - 1),
+        _ => None,
+    }).collect();
+    sheets.remove(i);
+    package.set_sheets(&sheets, &moved)?;
+    rename_references(&mut package, sheetname, None)?;
+    if let Some(calc_chain) = package.workbook_part("calcChain")? {
+        package.remove_workbook_part(&calc_chain)?;
+        package.set_full_calc_on_load()?;
+    }
+    package.save(path)
+}
+
+// remove those of `parts` no relationship refers to any more, then in turn the parts they referred to
+fn remove_unused_parts(package: &mut Package, mut parts: Vec<String>) -> Result<(), ExcelError> {
+    while !parts.is_empty() {
+        let linked = package.linked_parts()?;
+        let mut next = Vec::new();
+        for part in parts {
+            if !linked.contains(&part) && package.contains(&part) {
+                next.extend(package.related_parts(&part)?);
+                package.remove_part(&part)?;
+            }
+        }
+        parts = next;
+    }
+    Ok(())
+}
+
+// `sheetnames` lists every sheet in the new order
+pub fn reorder_sheets_writer(path: &str, sheetnames: &[&str]) -> Result<(), ExcelError> {
+    let mut package = Package::open(path)?;
+    let sheets = package.sheets()?;
+    let mut moved = vec![None; sheets.len()];
+    let mut reordered = Vec::with_capacity(sheets.len());
+    for (new, name) in sheetnames.iter().enumerate() {
+        let old = sheet_position(&sheets, name)?;
+        moved[old] = Some(new);
+        reordered.push(sheets[old].borrow());
+    }
+    package.set_sheets(&reordered, &moved)?;
+    package.save(path)
+}
+
+fn sheet_position(sheets: &[BytesStart], sheetname: &str) -> Result<usize, ExcelError> {
+    for (i, sheet) in sheets.iter().enumerate() {
+        if attribute(sheet, b"name")?.as_deref() == Some(sheetname) {
+            return Ok(i);
+        }
+    }
+    Err(ExcelError::SheetNotFound(sheetname.to_string()))
+}
+
+
+// The shared string table of a package, extended in place with new strings.
+struct SharedStrings {
+    part: String,
+    xml: Option<Vec<u8>>, // None if the package has no table yet
+    index: HashMap<String, u32>, // plain (not rich text) entries
+    unique: u32,
+    count: Option<i64>,
+    added: Vec<String>,
+}
+
+impl SharedStrings {
+    fn load(package: &mut Package) -> Result<Self, ExcelError> {
+        let part = package.workbook_part("sharedStrings")?.unwrap_or_else(|| SHARED_STRINGS.to_string());
+        let mut strings = Self {
+            part,
+            xml: None,
+            index: HashMap::new(),
+            unique: 0,
+            count: None,
+            added: Vec::new(),
+        };
+        if !package.contains(&strings.part) {
+            return Ok(strings);
+        }
+        let xml = package.read(&strings.part)?;
+        let mut reader = Reader::from_reader(xml.as_slice());
+        let mut depth = 0;
+        let mut text: Option<String> = None; // plain text of the current <si>
+        let mut in_text = false;
+        loop {
+            match reader.read_event().map_err(xml_error)? {
+                Event::Start(ref e) => {
+                    depth += 1;
+                    match e.local_name().as_ref() {
+                        b"sst" => strings.count = attribute(e, b"count")?.and_then(|c| c.parse().ok()),
+                        b"si" => text = Some(String::new()),
+                        b"t" if depth == 3 => in_text = true,
+                        b"r" => text = None, // rich text runs are never reused
+                        _ => {}
+                    }
+                }
+                Event::Empty(ref e) if e.local_name().as_ref() == b"si" => {
+                    strings.index.entry(String::new()).or_insert(strings.unique);
+                    strings.unique += 1;
+                }
+                Event::Text(ref e) if in_text => {
+                    if let Some(t) = text.as_mut() {
+                        t.push_str(&e.xml10_content().map_err(xml_error)?);
+                    }
+                }
+                Event::GeneralRef(ref e) if in_text => {
+                    if let Some(t) = text.as_mut() {
+                        let name = e.xml10_content().map_err(xml_error)?;
+                        t.push_str(&unescape(&format!("&{};", name)).map_err(xml_error)?);
+                    }
+                }
+                Event::End(ref e) => {
+                    depth -= 1;
+                    match e.local_name().as_ref() {
+                        b"t" => in_text = false,
+                        b"si" => {
+                            if let Some(t) = text.take() {
+                                strings.index.entry(t).or_insert(strings.unique);
+                            }
+                            strings.unique += 1;
+                        }
+                        _ => {}
+                    }
+                }
+                Event::Eof => break,
+                _ => {}
+            }
+        }
+        strings.xml = Some(xml);
+        Ok(strings)
+    }
+
+    // index of `s` in the table, appending it when missing
+    fn get_or_insert(&mut self, s: &str) -> u32 {
+        if let Some(&i) = self.index.get(s) {
+            return i;
+        }
+        let i = self.unique;
+        self.index.insert(s.to_string(), i);
+        self.added.push(s.to_string());
+        self.unique += 1;
+        i
+    }
+
+    fn add_refs(&mut self, n: i64) {
+        if let Some(count) = self.count.as_mut() {
+            *count += n;
+        }
+    }
+
+    fn store(self, package: &mut Package) -> Result<(), ExcelError> {
+        let xml = match self.xml {
+            Some(ref xml) => xml.clone(),
+            None if self.added.is_empty() => return Ok(()),
+            None => {
+                package.add_workbook_part(&self.part, "sharedStrings", SHARED_STRINGS_TYPE)?;
+                br#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<sst xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main"></sst>"#.to_vec()
+            }
+        };
+
+        let mut reader = Reader::from_reader(xml.as_slice());
+        let mut writer = Writer::new(Vec::with_capacity(xml.len()));
+        loop {
+            match reader.read_event().map_err(xml_error)? {
+                Event::Start(ref e) if e.local_name().as_ref() == b"sst" => {
+                    let mut start = without_attribute(&without_attribute(e, b"count"), b"uniqueCount");
+                    if let Some(count) = self.count {
+                        start.push_attribute(("count", count.max(0).to_string().as_str()));
+                    }
+                    start.push_attribute(("uniqueCount", self.unique.to_string().as_str()));
+                    writer.write_event(Event::Start(start))?;
+                }
+                Event::End(ref e) if e.local_name().as_ref() == b"sst" => {
+                    for s in &self.added {
+                        writer.create_element("si").write_inner_content(|w| write_text(w, s))?;
+                    }
+                    writer.write_event(Event::End(e.borrow()))?;
+                }
+                Event::Eof => break,
+                e => writer.write_event(e)?,
+            }
+        }
+        package.write(&self.part, writer.into_inner());
+        Ok(())
+    }
+}
+
+
+// The cell formats (<cellXfs>) of a package, extended in place so that dates written
+// into cells with the General number format show as dates. Packages without a styles
+// part are left alone; their dates show as serial numbers.
+struct CellFormats {
+    part: Option<String>,
+    xfs: Vec<Vec<Event<'static>>>, // each <xf> with its alignment and protection
+    dates: HashMap<(u32, DateKind), u32>, // xf of the cell and kind of date -> xf added for it
+    added: Vec<Vec<Event<'static>>>,
+}
+
+impl CellFormats {
+    fn load(package: &mut Package) -> Result<Self, ExcelError> {
+        let mut formats = Self { part: None, xfs: Vec::new(), dates: HashMap::new(), added: Vec::new() };
+        let part = match package.workbook_part("styles")? {
+            Some(part) if package.contains(&part) => part,
+            _ => return Ok(formats),
+        };
+        let xml = package.read(&part)?;
+        let mut reader = Reader::from_reader(xml.as_slice());
+        let mut in_cell_xfs = false;
+        let mut xf: Option<Vec<Event>> = None; // the <xf> being read
+        loop {
+            let event = reader.read_event().map_err(xml_error)?.into_owned();
+            if let Some(ref mut events) = xf {
+                let end = matches!(event, Event::End(ref e) if e.local_name().as_ref() == b"xf");
+                events.push(event);
+                if end {
+                    formats.xfs.extend(xf.take());
+                }
+                continue;
+            }
+            match event {
+                Event::Start(ref e) if e.local_name().as_ref() == b"cellXfs" => in_cell_xfs = true,
+                Event::End(ref e) if e.local_name().as_ref() == b"cellXfs" => in_cell_xfs = false,
+                Event::Start(ref e) if in_cell_xfs && e.local_name().as_ref() == b"xf" => xf = Some(vec![event]),
+                Event::Empty(ref e) if in_cell_xfs && e.local_name().as_ref() == b"xf" => formats.xfs.push(vec![event]),
+                Event::Eof => break,
+                _ => {}
+            }
+        }
+        formats.part = Some(part);
+        Ok(formats)
+    }
+
+    // the xf to give a date cell formatted with xf `s`, None when it already has a number format.
+    // It is `s` with the kind's built-in format.
+    fn date_style(&mut self, s: u32, kind: DateKind) -> Option<u32> {
+        if let Some(&i) = self.dates.get(&(s, kind)) {
+            return Some(i);
+        }
+        let mut xf = self.xfs.get(s as usize)?.clone();
+        let start = match xf.first_mut()? {
+            Event::Start(e) | Event::Empty(e) => e,
+            _ => return None,
+        };
+        if attribute(start, b"numFmtId").ok().flatten().is_some_and(|id| id != "0") {
+            return None;
+        }
+        let with_format = replace_attribute(start, "numFmtId", &kind.format_id().to_string());
+        *start = replace_attribute(&with_format, "applyNumberFormat", "1");
+        let i = (self.xfs.len() + self.added.len()) as u32;
+        self.added.push(xf);
+        self.dates.insert((s, kind), i);
+        Some(i)
+    }
+
+    fn store(self, package: &mut Package) -> Result<(), ExcelError> {
+        let part = match self.part {
+            Some(ref part) if !self.added.is_empty() => part,
+            _ => return Ok(()),
+        };
+        let xml = package.read(part)?;
+        let mut reader = Reader::from_reader(xml.as_slice());
+        let mut writer = Writer::new(Vec::with_capacity(xml.len()));
+        loop {
+            match reader.read_event().map_err(xml_error)? {
+                Event::Start(ref e) if e.local_name().as_ref() == b"cellXfs" => {
+                    let count = (self.xfs.len() + self.added.len()).to_string();
+                    writer.write_event(Event::Start(replace_attribute(e, "count", &count)))?;
+                }
+                Event::End(ref e) if e.local_name().as_ref() == b"cellXfs" => {
+                    for event in self.added.iter().flatten() {
+                        writer.write_event(event.borrow())?;
+                    }
+                    writer.write_event(Event::End(e.borrow()))?;
+                }
+                Event::Eof => break,
+                e => writer.write_event(e)?,
+            }
+        }
+        package.write(part, writer.into_inner());
+        Ok(())
+    }
+}
+
+
+// Rewrites <sheetData> so that it carries the given cells, leaving everything else untouched.
+struct SheetWriter<'s> {
+    strings: &'s mut SharedStrings,
+    formats: &'s mut CellFormats,
+    formulas_removed: bool,
+    formulas_added: bool,
+}
+
+// a <c> element being replaced, written once its original content has been skipped
+struct Replaced<'a> {
+    start: BytesStart<'static>,
+    col: u32,
+    value: &'a CellInput,
+}
+
+impl<'s> SheetWriter<'s> {
+    fn new(strings: &'s mut SharedStrings, formats: &'s mut CellFormats) -> Self {
+        Self { strings, formats, formulas_removed: false, formulas_added: false }
+    }
+
+    fn update(&mut self, xml: &[u8], cells: &Cells) -> Result<Vec<u8>, ExcelError> {
+        let mut reader = Reader::from_reader(xml);
+        let mut writer = Writer::new(Vec::with_capacity(xml.len()));
+        let mut rows = cells.iter().peekable();
+        let mut cols: Option<Cols> = None; // pending cells of the current row
+        let mut next_row = 0; // rows and cells may omit their "r" attribute
+        let mut next_col = 0;
+        let mut cur_row = 0;
+        let mut cur_col = 0;
+        let mut replaced: Option<Replaced> = None;
+        let mut depth = 0; // inside a replaced <c> element
+        // shared formulas whose first cell is overwritten; the other cells get a formula of their own
+        let orphaned = orphaned_shared_formulas(xml, cells)?;
+        let mut skip_formula = false;
+
+        loop {
+            let event = reader.read_event().map_err(xml_error)?;
+            if let Some(ref r) = replaced {
+                match event {
+                    Event::Start(ref e) | Event::Empty(ref e) if e.local_name().as_ref() == b"f" => {
+                        self.formulas_removed = true;
+                        if let Event::Start(_) = event {
+                            depth += 1;
+                        }
+                    }
+                    Event::Start(_) => depth += 1,
+                    Event::End(_) if depth > 0 => depth -= 1,
+                    Event::End(_) => {
+                        self.write_cell(&mut writer, cur_row, r.col, r.value, &r.start)?;
+                        replaced = None;
+                    }
+                    Event::Eof => break,
+                    _ => {}
+                }
+                continue;
+            }
+            if skip_formula {
+                if let Event::End(_) = event {
+                    skip_formula = false;
+                }
+                continue;
+            }
+            match event {
+                Event::Start(ref e) | Event::Empty(ref e) if e.local_name().as_ref() == b"f" && !orphaned.is_empty() => {
+                    let master = match attribute(e, b"si")? {
+                        Some(si) if attribute(e, b"t")?.as_deref() == Some("shared") => orphaned.get(&si),
+                        _ => None,
+                    };
+                    match master {
+                        Some(&(at, ref text)) => {
+                            let rows = i64::from(cur_row) - i64::from(at.row);
+                            let cols = i64::from(cur_col) - i64::from(at.col);
+                            let text = formula::shift(text, rows, cols);
+                            writer.create_element("f").write_text_content(BytesText::new(&text))?;
+                            skip_formula = matches!(event, Event::Start(_));
+                        }
+                        None => writer.write_event(event.borrow())?,
+                    }
+                }
+                Event::Empty(ref e) if e.local_name().as_ref() == b"dimension" => {
+                    let dimension = update_dimension(e, cells)?;
+                    writer.write_event(Event::Empty(dimension))?;
+                }
+                Event::Empty(ref e) if e.local_name().as_ref() == b"sheetData" => {
+                    writer.write_event(Event::Start(e.borrow()))?;
+                    self.write_rows(&mut writer, &mut rows, MAX_ROWS)?;
+                    writer.write_event(Event::End(e.to_end()))?;
+                }
+                Event::End(ref e) if e.local_name().as_ref() == b"sheetData" => {
+                    self.write_rows(&mut writer, &mut rows, MAX_ROWS)?;
+                    writer.write_event(Event::End(e.borrow()))?;
+                }
+                Event::Start(ref e) | Event::Empty(ref e) if e.local_name().as_ref() == b"row" => {
+                    cur_row = row_index(e, next_row)?;
+                    next_row = cur_row + 1;
+                    next_col = 0;
+                    self.write_rows(&mut writer, &mut rows, cur_row)?;
+                    let is_empty = matches!(event, Event::Empty(_));
+                    match rows.next_if(|&(r, _)| *r == cur_row) {
+                        Some((_, row_cells)) => {
+                            // spans is only an optimisation hint and may no longer be accurate
+                            let start = without_attribute(e, b"spans");
+                            writer.write_event(Event::Start(start.borrow()))?;
+                            if is_empty {
+                                let mut pending = row_cells.iter().peekable();
+                                self.write_cells(&mut writer, cur_row, &mut pending, MAX_COLS)?;
+                                writer.write_event(Event::End(start.to_end()))?;
+                            } else {
+                                cols = Some(row_cells.iter().peekable());
+                            }
+                        }
+                        None => writer.write_event(event.borrow())?,
+                    }
+                }
+                Event::End(ref e) if e.local_name().as_ref() == b"row" => {
+                    if let Some(mut pending) = cols.take() {
+                        self.write_cells(&mut writer, cur_row, &mut pending, MAX_COLS)?;
+                    }
+                    writer.write_event(Event::End(e.borrow()))?;
+                }
+                Event::Start(ref e) | Event::Empty(ref e) if e.local_name().as_ref() == b"c" => {
+                    let col = col_index(e, next_col)?;
+                    cur_col = col;
+                    next_col = col + 1;
+                    if let Some(pending) = cols.as_mut() {
+                        self.write_cells(&mut writer, cur_row, pending, col)?;
+                        if let Some((_, value)) = pending.next_if(|&(c, _)| *c == col) {
+                            if attribute(e, b"t")?.as_deref() == Some("s") {
+                                self.strings.add_refs(-1);
+                            }
+                            match event {
+                                Event::Start(ref e) => {
+                                    replaced = Some(Replaced { start: e.to_owned(), col, value });
+                                }
+                                _ => self.write_cell(&mut writer, cur_row, col, value, e)?,
+                            }
+                            continue;
+                        }
+                    }
+                    writer.write_event(event.borrow())?;
+                }
+                Event::Eof => break,
+                e => writer.write_event(e)?,
+            }
+        }
+        Ok(writer.into_inner())
+    }
+
+    // write all pending rows located before `until`
+    fn write_rows<W: Write>(&mut self, writer: &mut Writer<W>, rows: &mut Rows, until: u32) -> io::Result<()> {
+        while let Some((&row, row_cells)) = rows.next_if(|&(r, _)| *r < until) {
+            let r = (row + 1).to_string();
+            let start = BytesStart::new("row").with_attributes(vec![("r", r.as_str())]);
+            writer.write_event(Event::Start(start.borrow()))?;
+            self.write_cells(writer, row, &mut row_cells.iter().peekable(), MAX_COLS)?;
+            writer.write_event(Event::End(start.to_end()))?;
+        }
+        Ok(())
+    }
+
+    // write all pending cells of a row located before `until`
+    fn write_cells<W: Write>(&mut self, writer: &mut Writer<W>, row: u32, cols: &mut Cols, until: u32)
+        -> io::Result<()>
+    {
+        let blank = BytesStart::new("c");
+        while let Some((&col, value)) = cols.next_if(|&(c, _)| *c < until) {
+            self.write_cell(writer, row, col, value, &blank)?;
+        }
+        Ok(())
+    }
+
+    // write a cell, keeping the style of the `original` element
+    fn write_cell<W: Write>(&mut self, writer: &mut Writer<W>, row: u32, col: u32, input: &CellInput, original: &BytesStart)
+        -> io::Result<()>
+    {
+        let r = Address::new(row, col).to_string();
+        let mut c = BytesStart::new("c");
+        c.push_attribute(("r", r.as_str()));
+        let mut style = original.attributes().flatten().find(|a| a.key.as_ref() == b"s")
+            .map(|a| String::from_utf8_lossy(&a.value).into_owned());
+        let formula = input.formula_text();
+        let value = input.value().unwrap_or(&Data::Empty);
+        let (t, v) = match value {
+            Data::Empty if formula.is_some() => (None, None),
+            Data::Empty => {
+                if let Some(style) = style {
+                    c.push_attribute(("s", style.as_str()));
+                    writer.write_event(Event::Empty(c))?; // a blank cell that only keeps its format
+                }
+                return Ok(());
+            }
+            Data::Int(i) => (None, Some(i.to_string())),
+            Data::Float(f) => (None, Some(f.to_string())),
+            Data::DateTime(d) => {
+                let s = style.as_deref().and_then(|s| s.parse().ok()).unwrap_or(0);
+                if let Some(date) = DateKind::of(value).and_then(|kind| self.formats.date_style(s, kind)) {
+                    style = Some(date.to_string());
+                }
+                (None, Some(d.as_f64().to_string()))
+            }
+            Data::Bool(b) => (Some("b"), Some(if *b { "1" } else { "0" }.to_string())),
+            Data::Error(e) => (Some("e"), Some(e.to_string())),
+            // the text result of a formula is stored in the cell, not in the shared strings
+            Data::String(s) | Data::DateTimeIso(s) | Data::DurationIso(s) if formula.is_some() => {
+                (Some("str"), Some(s.clone()))
+            }
+            Data::String(s) | Data::DateTimeIso(s) | Data::DurationIso(s) => {
+                self.strings.add_refs(1);
+                (Some("s"), Some(self.strings.get_or_insert(s).to_string()))
+            }
+        };
+        if let Some(style) = style {
+            c.push_attribute(("s", style.as_str()));
+        }
+        if let Some(t) = t {
+            c.push_attribute(("t", t));
+        }
+        writer.write_event(Event::Start(c.borrow()))?;
+        if let Some(formula) = formula {
+            let mut f = writer.create_element("f");
+            if let Ok(Some(range)) = input.array_range(row as usize, col as usize) {
+                let range = range.to_string();
+                f = f.with_attributes(vec![("t", "array"), ("ref", range.as_str())]);
+            }
+            f.write_text_content(BytesText::new(formula))?;
+            self.formulas_added = true;
+        }
+        if let Some(v) = v {
+            writer.create_element("v").write_text_content(BytesText::new(&v))?;
+        }
+        writer.write_event(Event::End(c.to_end()))?;
+        Ok(())
+    }
+}
+
+// zero-based row of a <row> element, which may leave out its "r" attribute
+fn row_index(e: &BytesStart, next_row: u32) -> Result<u32, ExcelError> {
+    match attribute(e, b"r")? {
+        Some(r) => r.parse::<u32>().ok().and_then(|r| r.checked_sub(1))
+            .ok_or(ExcelError::InvalidPackage("invalid row number")),
+        None => Ok(next_row),
+    }
+}
+
+// zero-based column of a <c> element, which may leave out its "r" attribute
+fn col_index(e: &BytesStart, next_col: u32) -> Result<u32, ExcelError> {
+    match attribute(e, b"r")? {
+        Some(r) => r.parse::<Address>().map(|a| a.col).map_err(|_| ExcelError::InvalidPackage("invalid cell reference")),
+        None => Ok(next_col),
+    }
+}
+
+// Shared formulas (<f t="shared" ref=".." si="..">text</f>, the other cells of the range
+// only carry the si) whose first cell is among `cells`: si -> first cell and formula text
+fn orphaned_shared_formulas(xml: &[u8], cells: &Cells) -> Result<HashMap<String, (Address, String)>, ExcelError> {
+    let mut orphaned = HashMap::new();
+    let mut reader = Reader::from_reader(xml);
+    let (mut next_row, mut next_col, mut cur_row, mut cur_col) = (0, 0, 0, 0);
+    let mut master: Option<(String, String)> = None; // si and text of the formula being read
+    loop {
+        match reader.read_event().map_err(xml_error)? {
+            Event::Start(ref e) | Event::Empty(ref e) if e.local_name().as_ref() == b"row" => {
+                cur_row = row_index(e, next_row)?;
+                next_row = cur_row + 1;
+                next_col = 0;
+            }
+            Event::Start(ref e) | Event::Empty(ref e) if e.local_name().as_ref() == b"c" => {
+                cur_col = col_index(e, next_col)?;
+                next_col = cur_col + 1;
+            }
+            Event::Start(ref e) if e.local_name().as_ref() == b"f" => {
+                let replaced = cells.get(&cur_row).is_some_and(|row| row.contains_key(&cur_col));
+                let shared = attribute(e, b"t")?.as_deref() == Some("shared") && attribute(e, b"ref")?.is_some();
+                if let (true, true, Some(si)) = (replaced, shared, attribute(e, b"si")?) {
+                    master = Some((si, String::new()));
+                }
+            }
+            Event::Text(ref e) => {
+                if let Some((_, ref mut text)) = master {
+                    text.push_str(&e.xml10_content().map_err(xml_error)?);
+                }
+            }
+            Event::GeneralRef(ref e) => {
+                if let Some((_, ref mut text)) = master {
+                    let name = e.xml10_content().map_err(xml_error)?;
+                    text.push_str(&unescape(&format!("&{};", name)).map_err(xml_error)?);
+                }
+            }
+            Event::End(ref e) if e.local_name().as_ref() == b"f" => {
+                if let Some((si, text)) = master.take() {
+                    orphaned.insert(si, (Address::new(cur_row, cur_col), text));
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(orphaned)
+}
+
+// the ranges of the array formulas of a sheet
+fn array_formulas(xml: &[u8]) -> Result<Vec<CellRange>, ExcelError> {
+    let mut ranges = Vec::new();
+    let mut reader = Reader::from_reader(xml);
+    loop {
+        match reader.read_event().map_err(xml_error)? {
+            Event::Start(ref e) | Event::Empty(ref e) if e.local_name().as_ref() == b"f" => {
+                let range = match attribute(e, b"ref")? {
+                    Some(range) if attribute(e, b"t")?.as_deref() == Some("array") => range,
+                    _ => continue,
+                };
+                ranges.push(range.parse()?);
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(ranges)
+}
+
+fn write_text<W: Write>(writer: &mut Writer<W>, text: &str) -> io::Result<()> {
+    let t = writer.create_element("t");
+    let t = if text.trim() != text { t.with_attribute(("xml:space", "preserve")) } else { t };
+    t.write_text_content(BytesText::new(text))?;
+    Ok(())
+}
+
+// grow <dimension ref="..."> so that it covers the written cells
+fn update_dimension(e: &BytesStart, cells: &Cells) -> Result<BytesStart<'static>, ExcelError> {
+    let (first, last) = match (cells.keys().next(), cells.keys().next_back()) {
+        (Some(&first), Some(&last)) => (first, last),
+        _ => return Ok(e.to_owned()),
+    };
+    let left = cells.values().filter_map(|c| c.keys().next()).min().cloned().unwrap_or(0);
+    let right = cells.values().filter_map(|c| c.keys().next_back()).max().cloned().unwrap_or(0);
+    let mut dimension = CellRange::new(Address::new(first, left), Address::new(last, right));
+    if let Some(Ok(old)) = attribute(e, b"ref")?.map(|r| r.parse::<CellRange>()) {
+        dimension.start = Address::new(dimension.start.row.min(old.start.row), dimension.start.col.min(old.start.col));
+        dimension.end = Address::new(dimension.end.row.max(old.end.row), dimension.end.col.max(old.end.col));
+    }
+    let mut updated = without_attribute(e, b"ref");
+    updated.push_attribute(("ref", dimension.to_string().as_str()));
+    Ok(updated)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::{Read, Write};
+
+    use calamine::Data;
+    use chrono::NaiveDate;
+    use zip::write::SimpleFileOptions;
+    use zip::{ZipArchive, ZipWriter};
+
+    use super::*;
+    use super::super::dates::DateSystem;
+    use super::super::reader::{formulas_reader, open_reader};
+
+    const MAIN_NS: &str = r#"xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main" xmlns:r="http://schemas.openxmlformats.org/officeDocument/2006/relationships""#;
+
+    // a workbook as Excel saves a hand-made report: a styled header, a column width,
+    // a filled-down (shared) formula, defined names, data validation and a second
+    // sheet with formulas referring to the first
+    fn fixture(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("excelhandler-{}-{}.xlsx", std::process::id(), name));
+        let parts = [
+            ("[Content_Types].xml", r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types"><Default Extension="rels" ContentType="application/vnd.openxmlformats-package.relationships+xml"/><Default Extension="xml" ContentType="application/xml"/><Override PartName="/xl/workbook.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.sheet.main+xml"/><Override PartName="/xl/worksheets/sheet1.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.worksheet+xml"/><Override PartName="/xl/worksheets/sheet2.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.worksheet+xml"/><Override PartName="/xl/styles.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.styles+xml"/><Override PartName="/xl/sharedStrings.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.sharedStrings+xml"/></Types>"#.to_string()),
+            ("_rels/.rels", r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships"><Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument" Target="xl/workbook.xml"/></Relationships>"#.to_string()),
+            ("xl/workbook.xml", format!(r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<workbook {}><workbookPr/><bookViews><workbookView activeTab="0"/></bookViews><sheets><sheet name="Data" sheetId="1" r:id="rId1"/><sheet name="Report" sheetId="2" r:id="rId2"/></sheets><definedNames><definedName name="_xlnm.Print_Titles" localSheetId="0">Data!$1:$1</definedName><definedName name="Prices">Data!$B$2:$B$4</definedName></definedNames></workbook>"#, MAIN_NS)),
+            ("xl/_rels/workbook.xml.rels", r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships"><Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/worksheet" Target="worksheets/sheet1.xml"/><Relationship Id="rId2" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/worksheet" Target="worksheets/sheet2.xml"/><Relationship Id="rId3" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/styles" Target="styles.xml"/><Relationship Id="rId4" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/sharedStrings" Target="sharedStrings.xml"/></Relationships>"#.to_string()),
+            ("xl/styles.xml", format!(r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<styleSheet {}><numFmts count="1"><numFmt numFmtId="164" formatCode="0.000"/></numFmts><fonts count="2"><font><sz val="11"/><name val="Calibri"/></font><font><b/><sz val="11"/><name val="Calibri"/></font></fonts><fills count="2"><fill><patternFill patternType="none"/></fill><fill><patternFill patternType="gray125"/></fill></fills><borders count="1"><border><left/><right/><top/><bottom/><diagonal/></border></borders><cellStyleXfs count="1"><xf numFmtId="0" fontId="0" fillId="0" borderId="0"/></cellStyleXfs><cellXfs count="3"><xf numFmtId="0" fontId="0" fillId="0" borderId="0" xfId="0"/><xf numFmtId="0" fontId="1" fillId="0" borderId="0" xfId="0" applyFont="1"/><xf numFmtId="164" fontId="0" fillId="0" borderId="0" xfId="0" applyNumberFormat="1"/></cellXfs><cellStyles count="1"><cellStyle name="Normal" xfId="0" builtinId="0"/></cellStyles></styleSheet>"#, MAIN_NS)),
+            ("xl/sharedStrings.xml", format!(r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<sst {} count="6" uniqueCount="6"><si><t>Item</t></si><si><t>Price</t></si><si><t>Total</t></si><si><t>pen</t></si><si><t>ink</t></si><si><t>cap</t></si></sst>"#, MAIN_NS)),
+            ("xl/worksheets/sheet1.xml", format!(r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<worksheet {}><dimension ref="A1:C4"/><cols><col min="1" max="1" width="20" customWidth="1"/></cols><sheetData><row r="1"><c r="A1" s="1" t="s"><v>0</v></c><c r="B1" s="1" t="s"><v>1</v></c><c r="C1" s="1" t="s"><v>2</v></c></row><row r="2"><c r="A2" t="s"><v>3</v></c><c r="B2" s="2"><v>1.5</v></c><c r="C2"><f t="shared" ref="C2:C4" si="0">B2*2</f><v>3</v></c></row><row r="3"><c r="A3" t="s"><v>4</v></c><c r="B3" s="2"><v>2</v></c><c r="C3"><f t="shared" si="0"/><v>4</v></c></row><row r="4"><c r="A4" t="s"><v>5</v></c><c r="B4" s="2"><v>3</v></c><c r="C4"><f t="shared" si="0"/><v>6</v></c></row></sheetData><dataValidations count="1"><dataValidation type="list" sqref="A2:A4"><formula1>"pen,ink,cap"</formula1></dataValidation></dataValidations></worksheet>"#, MAIN_NS)),
+            ("xl/worksheets/sheet2.xml", format!(r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<worksheet {}><dimension ref="A1:B1"/><sheetData><row r="1"><c r="A1"><f>SUM(Data!C2:C4)</f><v>13</v></c><c r="B1"><f>'Data'!B2&amp;" Data!B2"</f><v>1.5</v></c></row></sheetData><dataValidations count="1"><dataValidation type="list" sqref="C1"><formula1>Data!$A$2:$A$4</formula1></dataValidation></dataValidations></worksheet>"#, MAIN_NS)),
+        ];
+        let mut zip = ZipWriter::new(File::create(&path).unwrap());
+        for (name, xml) in &parts {
+            zip.start_file(*name, SimpleFileOptions::default()).unwrap();
+            zip.write_all(xml.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    // every part of a package, unzipped
+    fn parts(path: &str) -> BTreeMap<String, String> {
+        let mut archive = ZipArchive::new(File::open(path).unwrap()).unwrap();
+        let mut parts = BTreeMap::new();
+        for i in 0..archive.len() {
+            let mut file = archive.by_index(i).unwrap();
+            let mut xml = String::new();
+            file.read_to_string(&mut xml).unwrap();
+            parts.insert(file.name().to_string(), xml);
+        }
+        parts
+    }
+
+    #[test]
+    fn overwriting_a_shared_formula_keeps_the_rest_of_its_range() {
+        let path = fixture("shared");
+        let before = parts(&path);
+        let value = CellInput::from(Data::Float(10.0));
+        set_range_values_writer(&path, "Data", 1, 2, &[vec![value]]).unwrap();
+        let after = parts(&path);
+
+        let sheet = &after["xl/worksheets/sheet1.xml"];
+        assert!(sheet.contains(r#"<c r="C2"><v>10</v></c>"#));
+        assert!(sheet.contains(r#"<c r="C3"><f>B3*2</f><v>4</v></c>"#));
+        assert!(sheet.contains(r#"<c r="C4"><f>B4*2</f><v>6</v></c>"#));
+        assert!(!sheet.contains("si="));
+        // the rest of the sheet as it was
+        assert!(sheet.contains(r#"<cols><col min="1" max="1" width="20" customWidth="1"/></cols>"#));
+        assert!(sheet.contains(r#"<c r="B1" s="1" t="s"><v>1</v></c>"#));
+        assert!(sheet.contains(r#"<dataValidation type="list" sqref="A2:A4"><formula1>"pen,ink,cap"</formula1></dataValidation>"#));
+        // and every other part, defined names and styles included
+        for (name, xml) in &before {
+            if name != "xl/worksheets/sheet1.xml" && name != "xl/sharedStrings.xml" {
+                assert_eq!(&after[name], xml, "{} changed", name);
+            }
+        }
+        // and reads back with the formulas where they were
+        let mut reader = open_reader(&path).unwrap();
+        let formulas = formulas_reader(&mut reader, "Data").unwrap();
+        assert_eq!(formulas.get(Address::new(2, 2)), Some("B3*2"));
+        assert_eq!(formulas.get(Address::new(1, 2)), None);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn saving_replaces_the_workbook_through_a_temporary_file() {
+        let path = fixture("save");
+        let value = CellInput::from(Data::from("new"));
+        set_range_values_writer(&path, "Report", 4, 0, &[vec![value]]).unwrap();
+        let dir = std::fs::read_dir(std::env::temp_dir()).unwrap();
+        let name = std::path::Path::new(&path).file_name().unwrap().to_string_lossy().into_owned();
+        assert!(!dir.flatten().any(|entry| entry.file_name().to_string_lossy().starts_with(&format!(".~{}", name))));
+        assert!(parts(&path)["xl/sharedStrings.xml"].contains("<si><t>new</t></si>"));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn renaming_a_sheet_renames_the_references_to_it() {
+        let path = fixture("rename");
+        let before = parts(&path);
+        rename_sheet_writer(&path, "Data", "Input data").unwrap();
+        let after = parts(&path);
+
+        let workbook = &after["xl/workbook.xml"];
+        assert!(workbook.contains(r#"name="Input data""#) && !workbook.contains(r#"name="Data""#));
+        assert!(workbook.contains(r#"<definedName name="_xlnm.Print_Titles" localSheetId="0">'Input data'!$1:$1</definedName>"#));
+        assert!(workbook.contains(r#"<definedName name="Prices">'Input data'!$B$2:$B$4</definedName>"#));
+        let report = &after["xl/worksheets/sheet2.xml"];
+        assert!(report.contains("<f>SUM('Input data'!C2:C4)</f>"));
+        assert!(report.contains(r#"<f>'Input data'!B2&amp;" Data!B2"</f>"#)); // not inside strings
+        assert!(report.contains("<formula1>'Input data'!$A$2:$A$4</formula1>"));
+        for name in &["xl/styles.xml", "xl/worksheets/sheet1.xml", "xl/sharedStrings.xml"] {
+            assert_eq!(after[*name], before[*name], "{} changed", name);
+        }
+        let mut reader = open_reader(&path).unwrap();
+        let formulas = formulas_reader(&mut reader, "Report").unwrap();
+        assert_eq!(formulas.get(Address::new(0, 0)), Some("SUM('Input data'!C2:C4)"));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn deleting_a_sheet_removes_its_parts_and_references() {
+        let path = fixture("delete");
+        // Data has a drawing with a chart and a picture, and comments; Report a drawing with the same picture
+        let rels = |links: &[(&str, &str)]| {
+            let links: String = links.iter().enumerate()
+                .map(|(i, (kind, target))| format!(
+                    r#"<Relationship Id="rId{}" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/{}" Target="{}"/>"#,
+                    i + 1, kind, target))
+                .collect();
+            format!(r#"<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">{}</Relationships>"#, links)
+        };
+        let mut package = Package::open(&path).unwrap();
+        package.write("xl/worksheets/_rels/sheet1.xml.rels", rels(&[("drawing", "../drawings/drawing1.xml"), ("comments", "../comments1.xml")]).into_bytes());
+        package.write("xl/worksheets/_rels/sheet2.xml.rels", rels(&[("drawing", "../drawings/drawing2.xml")]).into_bytes());
+        package.write("xl/drawings/_rels/drawing1.xml.rels", rels(&[("chart", "../charts/chart1.xml"), ("image", "../media/image1.png")]).into_bytes());
+        package.write("xl/drawings/_rels/drawing2.xml.rels", rels(&[("image", "../media/image1.png")]).into_bytes());
+        for name in &["xl/drawings/drawing1.xml", "xl/drawings/drawing2.xml", "xl/charts/chart1.xml", "xl/comments1.xml", "xl/media/image1.png"] {
+            package.write(name, b"<x/>".to_vec());
+        }
+        let types = String::from_utf8(package.read("[Content_Types].xml").unwrap()).unwrap()
+            .replace("</Types>", r#"<Override PartName="/xl/drawings/drawing1.xml" ContentType="application/vnd.openxmlformats-officedocument.drawing+xml"/><Override PartName="/xl/charts/chart1.xml" ContentType="application/vnd.openxmlformats-officedocument.drawingml.chart+xml"/></Types>"#);
+        package.write("[Content_Types].xml", types.into_bytes());
+        package.save(&path).unwrap();
+
+        delete_sheet_writer(&path, "Data").unwrap();
+        let after = parts(&path);
+        for name in &["xl/worksheets/sheet1.xml", "xl/worksheets/_rels/sheet1.xml.rels", "xl/drawings/drawing1.xml",
+                      "xl/drawings/_rels/drawing1.xml.rels", "xl/charts/chart1.xml", "xl/comments1.xml"] {
+            assert!(!after.contains_key(*name), "{} kept", name);
+        }
+        for name in &["xl/drawings/drawing2.xml", "xl/media/image1.png", "xl/worksheets/sheet2.xml"] {
+            assert!(after.contains_key(*name), "{} removed", name);
+        }
+        assert!(!after["[Content_Types].xml"].contains("drawing1") && !after["[Content_Types].xml"].contains("chart1"));
+
+        let workbook = &after["xl/workbook.xml"];
+        assert!(!workbook.contains("_xlnm.Print_Titles")); // local to Data
+        assert!(workbook.contains(r#"<definedName name="Prices">#REF!</definedName>"#));
+        let report = &after["xl/worksheets/sheet2.xml"];
+        assert!(report.contains("<f>SUM(#REF!)</f>"));
+        assert!(report.contains(r#"<f>#REF!&amp;" Data!B2"</f>"#));
+        assert!(report.contains("<formula1>#REF!</formula1>"));
+        let reader = open_reader(&path).unwrap();
+        assert_eq!(calamine::Reader::sheet_names(&reader), vec!["Report".to_string()]);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn overwriting_inside_a_shared_formula_keeps_its_first_cell() {
+        let path = fixture("shared-inside");
+        let value = CellInput::from(Data::Float(10.0));
+        set_range_values_writer(&path, "Data", 2, 2, &[vec![value]]).unwrap();
+        let sheet = &parts(&path)["xl/worksheets/sheet1.xml"];
+        assert!(sheet.contains(r#"<c r="C2"><f t="shared" ref="C2:C4" si="0">B2*2</f><v>3</v></c>"#));
+        assert!(sheet.contains(r#"<c r="C3"><v>10</v></c>"#));
+        assert!(sheet.contains(r#"<c r="C4"><f t="shared" si="0"/><v>6</v></c>"#));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn array_formulas_clear_the_cells_they_cover() {
+        let path = fixture("array");
+        let range: CellRange = "B2:C3".parse().unwrap();
+        let value = CellInput::array_formula("A2:A3&B2:B3", &range);
+        set_range_values_writer(&path, "Data", 1, 1, &[vec![value]]).unwrap();
+        let sheet = &parts(&path)["xl/worksheets/sheet1.xml"];
+        assert!(sheet.contains(r#"<c r="B2" s="2"><f t="array" ref="B2:C3">A2:A3&amp;B2:B3</f></c>"#), "{}", sheet);
+        assert!(sheet.contains(r#"<c r="B3" s="2"/>"#));
+        assert!(!sheet.contains(r#"<c r="C2""#) && !sheet.contains(r#"<c r="C3""#), "{}", sheet);
+        assert!(sheet.contains(r#"<c r="C4"><f>B4*2</f><v>6</v></c>"#));
+
+        // only replaced as a whole, from its first cell
+        let before = parts(&path);
+        let one = [vec![CellInput::from(Data::Int(1))]];
+        let result = set_range_values_writer(&path, "Data", 2, 2, &one);
+        assert!(matches!(result, Err(ExcelError::ArrayFormulaPart(ref r)) if r.to_string() == "Data!B2:C3"));
+        assert_eq!(parts(&path), before);
+        set_range_values_writer(&path, "Data", 1, 1, &one).unwrap();
+        let sheet = &parts(&path)["xl/worksheets/sheet1.xml"];
+        assert!(sheet.contains(r#"<c r="B2" s="2"><v>1</v></c>"#) && !sheet.contains("<f t=\"array\""), "{}", sheet);
+        set_range_values_writer(&path, "Data", 2, 2, &one).unwrap();
+
+        let range = CellRange::new(Address::new(MAX_ROWS - 2, 0), Address::new(MAX_ROWS - 1, 0));
+        let value = CellInput::array_formula("A1:A2", &range);
+        let before = parts(&path);
+        let result = set_range_values_writer(&path, "Data", MAX_ROWS - 1, 0, &[vec![value]]);
+        assert!(matches!(result, Err(ExcelError::AddressOutOfRange { .. })));
+        assert_eq!(parts(&path), before);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn numbers_that_are_not_finite_are_rejected() {
+        let path = fixture("not-finite");
+        let before = parts(&path);
+        let data = [vec![CellInput::from(Data::Float(1.0)), CellInput::from(Data::Float(f64::NAN))]];
+        let result = set_range_values_writer(&path, "Data", 5, 0, &data);
+        assert!(matches!(result, Err(ExcelError::NonFiniteNumber { address, .. }) if address == Address::new(5, 1)));
+        let cached = [vec![CellInput::formula("1/0").with_cached(Data::Float(f64::INFINITY))]];
+        assert!(matches!(set_range_values_writer(&path, "Data", 5, 0, &cached), Err(ExcelError::NonFiniteNumber { .. })));
+        assert_eq!(parts(&path), before);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn dates_in_general_cells_get_a_date_format() {
+        let path = fixture("dates");
+        let date = CellInput::from(NaiveDate::from_ymd_opt(2024, 2, 29).unwrap()).resolve(DateSystem::V1900).unwrap();
+        let data = [vec![date.clone(), date.clone()], vec![date.clone(), date]];
+        set_range_values_writer(&path, "Data", 0, 1, &data).unwrap();
+        let after = parts(&path);
+
+        let styles = &after["xl/styles.xml"];
+        assert!(styles.contains(r#"<cellXfs count="5">"#), "{}", styles);
+        assert!(styles.contains(r#"<xf fontId="1" fillId="0" borderId="0" xfId="0" applyFont="1" numFmtId="14" applyNumberFormat="1"/>"#), "{}", styles);
+        assert!(styles.contains(r#"<xf fontId="0" fillId="0" borderId="0" xfId="0" numFmtId="14" applyNumberFormat="1"/></cellXfs>"#), "{}", styles);
+        let sheet = &after["xl/worksheets/sheet1.xml"];
+        assert!(sheet.contains(r#"<c r="B1" s="3"><v>45351</v></c><c r="C1" s="3"><v>45351</v></c>"#), "{}", sheet);
+        // a number format of its own is kept, and General cells without a style take xf 0's look
+        assert!(sheet.contains(r#"<c r="B2" s="2"><v>45351</v></c><c r="C2" s="4">"#), "{}", sheet);
+        let _ = std::fs::remove_file(&path);
+    }
+}
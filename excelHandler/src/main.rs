@@ -1,48 +1,213 @@
-use excelhandler::excel::{ExcelHandle, CellValue, Mode};
+use excelhandler::excel::{ExcelHandle, ExcelRead, ExcelError, CellValue, DataType, ReadOnly, ReadWrite, New};
+use excelhandler::excel::{Address, CellRange, CellInput, Scan, Predicate, Normalization};
+use excelhandler::excel::{CellStyle, Border, Align, DateValue};
+use chrono::{Duration, NaiveDate};
+use serde::Deserialize;
 use excelhandler::excel::is_writable;
 
-fn main() {
+#[derive(Deserialize)]
+struct Item {
+    #[serde(rename = "Name")]
+    name: String,
+    #[serde(rename = "Unit price")]
+    price: Option<f64>,
+    #[serde(rename = "Qty")]
+    quantity: u32,
+}
 
-    println!("{}", Mode::Read);
+fn main() -> Result<(), ExcelError> {
 
     let file_path = "sample/rust_sample.xlsx".to_string();
-    let ex = ExcelHandle::new(file_path, Mode::Read).expect("Failed to open file");
+    let ex = ExcelHandle::<ReadOnly>::new(file_path.clone())?.cache_limit(Some(1 << 20));
+    println!("{}", ex.mode());
 
     println!("Method: get_sheetnames");
-    let sheets = ex.get_sheetnames();
+    let sheets = ex.get_sheetnames()?;
     for sheet in &sheets {
         println!("{}", sheet);
     }
 
     println!("Method: find_cell");
-    let func_condition = |x: &CellValue| x == "foo";
-    let func_type = |x: &CellValue| if x.is_string() { func_condition(x) } else { false };
-    let rows = || (0..10).step_by(1);
-    let cols = || (1..10);
-    // let a = 0..10;
+    let func_type = Predicate::eq("foo");
+    let scan = Scan::new(0..10, 1..10);
     for sheet in sheets {
-        if let Some(address) = ex.find_cell(sheet.as_str(), &rows, &cols, &func_type) {
+        if let Some(address) = ex.find_cell(sheet.as_str(), &scan, &func_type)? {
             println!("{:?}", address);
         };
     }
+    let reversed = Scan::new(3..10, 3..10).rev_rows();
+    println!("{:?}", ex.find_cell("Sheet1", &reversed, Predicate::is_empty().not())?);
+    if let Err(e) = ex.find_cell("NoSuchSheet", &scan, &func_type) {
+        println!("{}", e);
+    }
 
     println!("Method: find_sheets");
-    let rows = || (0..10).step_by(1);
-    let cols = || (4..10);
-    let sheets = ex.find_sheets(&rows, &cols, &func_type);
+    let sheets = ex.find_sheets(&Scan::new(0..10, 4..10), &func_type)?;
     println!("{:?}", sheets);
+    let full_width = Predicate::eq("ｆｏｏ\u{3000}").normalized(Normalization::nfkc());
+    println!("{:?}", ex.find_sheets(&Scan::new(0..10, 4..10), &full_width)?);
+    ex.clear_cache();
+    println!("{:?}", ex.find_sheets_par(&Scan::new(0..10, 4..10), &full_width)?);
+
+    println!("Method: find_cell_fuzzy");
+    for m in ex.find_cell_fuzzy("Sheet3", &Scan::all(), "fo", 1)? {
+        println!("{} {:?} {} {:.2}", m.address, m.text, m.distance, m.score);
+    }
+    println!("{:?}", ex.find_sheets(&Scan::all(), &Predicate::fuzzy("fooo", 1))?);
+
+    println!("Method: find_all_cells, find_in_workbook");
+    let func = |x: &CellValue| x.is_bool();
+    println!("{:?}", ex.find_all_cells("Sheet1", &Scan::all(), func)?);
+    println!("{:?}", ex.find_in_workbook(&Scan::all().column_major(), &func_type)?);
+
+    println!("Method: cache_size, evict");
+    println!("{}", ex.cache_size() > 0);
+    ex.evict("Sheet2");
+    ex.clear_cache();
+    println!("{}", ex.cache_size());
+
+    println!("Method: stream_rows");
+    let mut stream = ex.stream_rows("Sheet3")?;
+    for row in stream.rows()?.take(2) {
+        println!("{:?}", row?);
+    }
+    println!("{:?}", stream.find_cell(&Scan::new(0.., 0..), &func_type)?);
+    let streaming = ExcelHandle::<ReadOnly>::new(file_path.clone())?.streaming(true);
+    println!("{:?}", streaming.find_cell("Sheet1", &Scan::new(0..10, 1..10), &func_type)?);
+
+    println!("Method: find_all_cells_in");
+    let range: CellRange = "Sheet1!F:F".parse()?;
+    let found = ex.find_all_cells_in(&range, Predicate::regex("^[a-z]+$")?)?;
+    println!("{}", found.iter().map(Address::to_string).collect::<Vec<_>>().join(" "));
+    println!("{:?}", Address::from_one_based(1, 3)?);
 
     println!("Method: iterate_row_values");
-    let rows = || (0..10).step_by(2);
-    let cols = || (5..);
-    let func = |x: &Vec<CellValue>| x.iter().all(|y| y.is_empty());
-    let values = ex.iterate_row_values("Sheet1", &rows, &cols, func);
+    let scan = Scan::new(0..10, 5..).step_rows(2);
+    let values = ex.iterate_row_values("Sheet1", &scan, Predicate::is_empty())?;
     for value in values {
         println!("{:?}", value);
     }
+    let never = |_: &Vec<CellValue>| false; // stops at the end of the used range
+    println!("{}", ex.iterate_row_values("Sheet1", &scan, never)?.len());
+
+    println!("Method: date_system, DateValue");
+    let system = ex.date_system();
+    let values = ex.iterate_row_values("Sheet1", &Scan::new(4..7, 8..9), never)?;
+    println!("{:?} {:?} {:?}", system, values[0][0].to_date(system), values[0][2].to_duration());
+
+    println!("Method: iter_row_values");
+    let (sheet1, sheet3) = (ex.worksheet("Sheet1")?, ex.worksheet("Sheet3")?);
+    let func = |_: &Vec<CellValue>| false;
+    let (scan1, scan3) = (Scan::new(5..6, 2..), Scan::new(5..6, 6..));
+    let values1 = sheet1.iter_row_values(&scan1, func);
+    let values3 = sheet3.iter_row_values(&scan3, func);
+    for (v1, v3) in values1.zip(values3).take(2) {
+        println!("{:?} {:?}", v1, v3);
+    }
+    let scan = Scan::new(4..7, 6..7);
+    let compacted = sheet3.iter_row_values(&scan, func).compact();
+    println!("{:?}", compacted.collect::<Vec<_>>());
+
+    println!("Method: set_range_values");
+    let write_path = std::env::temp_dir().join("rust_sample_write.xlsx");
+    std::fs::copy(&file_path, &write_path)?;
+    let ex = ExcelHandle::<ReadWrite>::new(write_path.to_string_lossy().into_owned())?;
+    let data = vec![
+        vec![CellValue::from("bar"), CellValue::Float(1.5)],
+        vec![CellValue::Bool(true), CellValue::Empty],
+    ];
+    let wrapped_func = is_writable(&ex, |ex, sheet| ex.set_range_values(sheet, 4, 9, &data));
+    wrapped_func("Sheet1")?;
+
+    ex.set_range_values_at("Sheet2", "A1".parse()?, &data)?;
+    let func = |_: &Vec<CellValue>| false;
+    println!("{:?}", ex.iterate_row_values("Sheet1", &Scan::new(4..6, 9..11), func)?);
+
+    println!("Method: set_cell, formulas, get_formula, find_formulas");
+    ex.set_cell("Sheet1", "L5".parse()?, CellInput::formula("=K5*2").with_cached(CellValue::Float(3.0)))?;
+    let range: CellRange = "L6:M7".parse()?;
+    ex.set_cell("Sheet1", range.start, CellInput::array_formula("{=K5:L5*2}", &range))?;
+    for (address, formula) in ex.formulas("Sheet1")?.iter() {
+        println!("{} ={}", address, formula);
+    }
+    println!("{:?}", ex.get_formula("Sheet1", "L5".parse()?)?);
+    println!("{:?}", ex.find_formulas("Sheet1", &Scan::all(), Predicate::contains("K5"))?);
 
-    let wrapped_func = is_writable(&ex, ExcelHandle::set_range_values);
-    wrapped_func("Sheet1").unwrap();
+    println!("Method: deserialize");
+    let items = vec![
+        vec![CellValue::from("Name"), CellValue::from("Unit price"), CellValue::from("Qty")],
+        vec![CellValue::from("pen"), CellValue::Float(1.2), CellValue::from("10")],
+        vec![CellValue::from("ink"), CellValue::Empty, CellValue::Int(3)],
+    ];
+    ex.add_sheet("Items", None)?;
+    ex.set_range_values_at("Items", "A2".parse()?, &items)?;
+    for item in ex.deserialize::<Item>("Items", 1)? {
+        println!("{} {:?} {}", item.name, item.price, item.quantity);
+    }
+    ex.set_cell("Items", "C4".parse()?, CellValue::from("three"))?;
+    if let Err(e) = ex.deserialize::<Item>("Items", 1) {
+        println!("{}", e);
+    }
+    ex.delete_sheet("Items")?;
+
+    println!("Method: add_sheet, rename_sheet, reorder_sheets, delete_sheet");
+    ex.add_sheet("Added", Some(1))?;
+    ex.rename_sheet("Added", "Renamed")?;
+    if let Err(e) = ex.rename_sheet("Renamed", "sheet1") {
+        println!("{}", e);
+    }
+    let mut order = ex.get_sheetnames()?;
+    order.reverse();
+    ex.reorder_sheets(&order.iter().map(String::as_str).collect::<Vec<_>>())?;
+    println!("{:?}", ex.get_sheetnames()?);
+    ex.delete_sheet("Renamed")?;
+    println!("{:?}", ex.get_sheetnames()?);
 
-    ex.set_range_values("Sheet1").unwrap();
+    println!("Method: save");
+    let create_path = std::env::temp_dir().join("rust_sample_create.xlsx");
+    let _ = std::fs::remove_file(&create_path);
+    let ex = ExcelHandle::<New>::new(create_path.to_string_lossy().into_owned())?;
+    ex.set_range_values("Sheet1", 0, 0, &data)?;
+    ex.set_cell("Sheet1", "C1".parse()?, CellInput::formula("B1*2").with_cached(CellValue::Float(3.0)))?;
+    let date = NaiveDate::from_ymd_opt(1900, 2, 28).unwrap_or_default();
+    let datetime = date.and_hms_opt(12, 30, 0).unwrap_or_default();
+    ex.set_cell("Sheet1", "A5".parse()?, date)?;
+    ex.append_row("Sheet1", &[CellInput::from(datetime), CellInput::from(Duration::minutes(90))])?;
+    if let Err(e) = ex.set_cell("Sheet1", "A7".parse()?, NaiveDate::from_ymd_opt(1899, 1, 1).unwrap_or_default()) {
+        println!("{}", e);
+    }
+    let header = CellStyle::new().bold().fill(0xDDEBF7).border(Border::Thin).align(Align::Center);
+    ex.define_style("header", header)?;
+    ex.set_range_values_styled("Sheet1", 3, 0, &[vec![CellValue::from("total")]], &ex.named_style("header")?)?;
+    ex.set_style("Sheet1", &"A1:C2".parse()?, &CellStyle::new().num_format("0.00"))?;
+    if let Err(e) = ex.named_style("footer") {
+        println!("{}", e);
+    }
+    ex.add_sheet("First", Some(0))?;
+    ex.save()?;
+    println!("{:?}", ex.get_sheetnames()?);
+    println!("{:?}", ex.iterate_row_values("Sheet1", &Scan::new(0..2, 0..3).row_major(), func)?);
+    println!("{:?}", ex.get_formula("Sheet1", "C1".parse()?)?);
+    let values = ex.iterate_row_values("Sheet1", &Scan::new(4..6, 0..2).row_major(), func)?;
+    println!("{:?} {:?} {:?}", values[0][0].to_date(ex.date_system()), values[1][0].to_datetime(ex.date_system()), values[1][1].to_duration());
+    ex.close()?;
+
+    println!("Method: new_constant_memory, append_row");
+    let _ = std::fs::remove_file(&create_path);
+    let ex = ExcelHandle::<New>::new_constant_memory(create_path.to_string_lossy().into_owned())?;
+    ex.append_row_styled("Report", &[CellValue::from("n"), CellValue::from("text")], &CellStyle::new().bold())?;
+    ex.set_style("Report", &"A5:B5".parse()?, &CellStyle::new().fill(0xFFFF00).num_format("0.0"))?;
+    for i in 0..3 {
+        ex.append_row("Report", &[CellValue::Int(i), CellValue::from(" <row> & more ")])?;
+    }
+    if let Err(e) = ex.set_range_values("Report", 0, 0, &data) {
+        println!("{}", e);
+    }
+    ex.save()?;
+    if let Err(e) = ex.set_style("Report", &"A1".parse()?, &CellStyle::new()) {
+        println!("{}", e);
+    }
+    ex.append_row("Report", &[CellInput::from(CellValue::Bool(true)), CellInput::formula("SUM(A1:A3)")])?;
+    println!("{:?}", ex.iterate_row_values("Report", &Scan::all().row_major(), never)?);
+    ex.close()
 }